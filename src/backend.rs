@@ -0,0 +1,267 @@
+//! Programs which actually draw the wallpaper
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    io::{ErrorKind, Read, Write},
+    os::fd::AsRawFd,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, trace};
+use serde::{
+    de::{self, Unexpected},
+    Deserialize, Deserializer,
+};
+
+use crate::config::{self, Config};
+use crate::daemon::DaemonArgs;
+use crate::error::Error;
+use crate::span::Slice;
+
+mod command;
+mod feh;
+mod hyprpaper;
+mod swaybg;
+mod swww;
+mod xwallpaper;
+
+pub use command::{CommandLine, WallpaperCommands};
+
+/// Everything a backend needs to show a wallpaper
+#[derive(Debug)]
+pub struct Job {
+    pub image: PathBuf,
+    /// Output to draw on, the outputs of the backend config if not set
+    pub output: Option<String>,
+    /// Part of the image to show instead of the whole image, cut out by the worker
+    pub slice: Option<Slice>,
+    /// Previously shown images, the most recent one last
+    pub history: VecDeque<PathBuf>,
+    /// How long a single command may run
+    pub timeout: Duration,
+}
+
+impl Job {
+    /// Outputs to draw on, all outputs if empty
+    fn outputs<'a>(&'a self, configured: &'a [String]) -> Vec<&'a str> {
+        match &self.output {
+            Some(output) => vec![output],
+            None => configured.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// Sets the wallpaper through some external program
+pub trait Backend: Debug + Send {
+    /// Show `job.image` as the wallpaper
+    fn apply(&mut self, job: &Job) -> Result<(), Error>;
+
+    /// Show one wallpaper made of several jobs, like the slices of a panorama
+    fn apply_all(&mut self, jobs: &[Job]) -> Result<(), Error> {
+        jobs.iter()
+            .map(|job| self.apply(job))
+            .fold(Ok(()), Result::and)
+    }
+}
+
+/// The `[backend]` table of the config, `name` selects the backend
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum BackendConfig {
+    Feh(feh::Options),
+    Swaybg(swaybg::Options),
+    Swww(swww::Options),
+    Hyprpaper(hyprpaper::Options),
+    Xwallpaper(xwallpaper::Options),
+    /// Runs `wallpaper_change_command`
+    #[default]
+    Command,
+}
+
+/// Values of `name`
+const NAMES: &[&str] = &[
+    "feh",
+    "swaybg",
+    "swww",
+    "hyprpaper",
+    "xwallpaper",
+    "command",
+];
+
+/// Options of the command backend, which are top level keys
+#[derive(Deserialize)]
+struct NoOptions {}
+
+impl<'de> Deserialize<'de> for BackendConfig {
+    /// Like an internally tagged enum, except that the options go through [`serde_ignored`],
+    /// which doesn't see the keys serde buffers to find the tag
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut table = toml::Table::deserialize(deserializer)?;
+        let name = match table.remove("name") {
+            Some(toml::Value::String(name)) => name,
+            Some(other) => {
+                return Err(de::Error::invalid_type(
+                    Unexpected::Other(other.type_str()),
+                    &"the name of a backend",
+                ))
+            }
+            None => return Err(de::Error::missing_field("name")),
+        };
+        let options = toml::Value::Table(table);
+        let warn = |path: serde_ignored::Path| config::warn_unknown("backend", &path);
+        let config = match name.as_str() {
+            "feh" => serde_ignored::deserialize(options, warn).map(BackendConfig::Feh),
+            "swaybg" => serde_ignored::deserialize(options, warn).map(BackendConfig::Swaybg),
+            "swww" => serde_ignored::deserialize(options, warn).map(BackendConfig::Swww),
+            "hyprpaper" => serde_ignored::deserialize(options, warn).map(BackendConfig::Hyprpaper),
+            "xwallpaper" => {
+                serde_ignored::deserialize(options, warn).map(BackendConfig::Xwallpaper)
+            }
+            "command" => {
+                serde_ignored::deserialize(options, warn).map(|NoOptions {}| BackendConfig::Command)
+            }
+            name => return Err(de::Error::unknown_variant(name, NAMES)),
+        };
+        config.map_err(de::Error::custom)
+    }
+}
+
+/// Create the backend selected by the config
+/// A change command given on the command line always uses the command backend
+pub fn create(args: &DaemonArgs, config: &Config) -> Box<dyn Backend> {
+    if args.wallpaper_change_command.is_some() {
+        return Box::new(command::CommandBackend::new(WallpaperCommands::new(
+            args, config,
+        )));
+    }
+    match &config.backend {
+        BackendConfig::Feh(options) => Box::new(feh::Feh::new(options.clone())),
+        BackendConfig::Swaybg(options) => Box::new(swaybg::Swaybg::new(options.clone())),
+        BackendConfig::Swww(options) => Box::new(swww::Swww::new(options.clone())),
+        BackendConfig::Hyprpaper(options) => Box::new(hyprpaper::Hyprpaper::new(options.clone())),
+        BackendConfig::Xwallpaper(options) => {
+            Box::new(xwallpaper::Xwallpaper::new(options.clone()))
+        }
+        BackendConfig::Command => Box::new(command::CommandBackend::new(WallpaperCommands::new(
+            args, config,
+        ))),
+    }
+}
+
+/// Run a program and wait for it to finish, killing it after `timeout`
+fn run(command: &mut Command, timeout: Duration) -> Result<(), Error> {
+    wait(command.stdout(Stdio::null()), None, timeout).map(|_| ())
+}
+
+/// Like [`run`], but returns what the program printed to stdout
+pub fn read_output(command: &mut Command, timeout: Duration) -> Result<String, Error> {
+    wait(command.stdout(Stdio::piped()), None, timeout)
+}
+
+/// Like [`read_output`], but writes `input` to the program's stdin
+pub fn communicate(
+    command: &mut Command,
+    input: Vec<u8>,
+    timeout: Duration,
+) -> Result<String, Error> {
+    wait(command.stdout(Stdio::piped()), Some(input), timeout)
+}
+
+fn wait(command: &mut Command, input: Option<Vec<u8>>, timeout: Duration) -> Result<String, Error> {
+    trace!("Calling {:?}", command);
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    let mut child = command
+        .stdin(stdin)
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::Command(format!("Couldn't start {:?}: {err}", command)))?;
+
+    if let Some(input) = input {
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // The program may exit without reading everything
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+
+    // Read on other threads, so a full pipe can't block the program
+    let deadline = Instant::now() + timeout;
+    let stderr = read_until(child.stderr.take().expect("stderr is piped"), deadline);
+    let stdout = child
+        .stdout
+        .take()
+        .map(|stdout| read_until(stdout, deadline));
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::Command(format!(
+                "{:?} didn't finish within {}s and was killed",
+                command,
+                timeout.as_secs_f32()
+            )));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let stderr = stderr.join().unwrap_or_default();
+    let stderr = stderr.trim();
+    if status.success() {
+        debug!("{:?} exited with {status}", command);
+        if !stderr.is_empty() {
+            debug!("stderr: {stderr}");
+        }
+        Ok(stdout
+            .map(|stdout| stdout.join().unwrap_or_default())
+            .unwrap_or_default())
+    } else {
+        Err(Error::Command(format!(
+            "{:?} exited with {status}: {stderr}",
+            command
+        )))
+    }
+}
+
+/// Read the pipe until it is closed or `deadline` has passed, then close it
+///
+/// Background processes started by a command may hold on to its pipes long after it exited.
+fn read_until<P>(mut pipe: P, deadline: Instant) -> thread::JoinHandle<String>
+where
+    P: Read + AsRawFd + Send + 'static,
+{
+    thread::spawn(move || {
+        let fd = pipe.as_raw_fd();
+        // SAFETY: `fd` stays open as long as `pipe` lives
+        unsafe {
+            libc::fcntl(
+                fd,
+                libc::F_SETFL,
+                libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK,
+            );
+        }
+        let mut output = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            match pipe.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => output.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(_) => break,
+            }
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    })
+}
@@ -0,0 +1,178 @@
+use std::{ffi::OsString, path::Path, process::Command};
+
+use serde::Deserialize;
+
+use super::{run, Backend, Job};
+use crate::config::Config;
+use crate::daemon::DaemonArgs;
+use crate::error::Error;
+
+/// Placeholder for the path of the image
+const WALLPAPER: &str = "%wallpaper%";
+/// Placeholder for the name of the output, empty when all outputs share one rotation
+const MONITOR: &str = "%monitor%";
+
+/// Operators like `&&`, `|` or `2>` only work when running through a shell
+fn is_shell_operator(word: &str) -> bool {
+    let operator = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    !operator.is_empty() && operator.chars().all(|c| "&|;<>".contains(c))
+}
+
+/// A command as written in the config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CommandLine {
+    /// Split into words like a shell would, or passed to `sh -c` in shell mode
+    Line(String),
+    /// Program and arguments
+    Argv(Vec<String>),
+    /// Several commands run one after another
+    Sequence(Vec<Vec<String>>),
+}
+
+impl CommandLine {
+    /// The program with its arguments, `key` names the config key in errors
+    pub fn single(&self, key: &str) -> Result<Command, Error> {
+        let argv = match self {
+            CommandLine::Line(line) => shell_words::split(line)
+                .map_err(|err| Error::Command(format!("Can't parse '{line}': {err}")))?,
+            CommandLine::Argv(argv) => argv.clone(),
+            CommandLine::Sequence(_) => {
+                return Err(Error::Command(format!("{key} has to be a single command")))
+            }
+        };
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| Error::Command(format!("{key} is empty")))?;
+        let mut command = Command::new(program);
+        command.args(args);
+        Ok(command)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct WallpaperCommands {
+    pub wallpaper_cmd: CommandLine,
+    pub wallpaper_post_cmd: Option<CommandLine>,
+    pub wallpaper_post_offset: Option<usize>,
+    /// Run [`CommandLine::Line`] through `sh -c`
+    pub shell: bool,
+}
+
+impl WallpaperCommands {
+    pub fn new(args: &DaemonArgs, config: &Config) -> Self {
+        let wallpaper_cmd = args
+            .wallpaper_change_command
+            .clone()
+            .map(CommandLine::Line)
+            .unwrap_or_else(|| config.wallpaper_change_command.clone());
+        let wallpaper_post_cmd = args
+            .wallpaper_post_change_command
+            .clone()
+            .map(CommandLine::Line)
+            .or_else(|| config.wallpaper_post_change_command.clone());
+        let wallpaper_post_offset = args
+            .wallpaper_post_change_offset
+            .as_ref()
+            .or(config.wallpaper_post_change_offset.as_ref());
+
+        WallpaperCommands {
+            wallpaper_cmd,
+            wallpaper_post_cmd,
+            wallpaper_post_offset: wallpaper_post_offset.cloned(),
+            shell: args.wallpaper_command_shell || config.wallpaper_command_shell,
+        }
+    }
+
+    /// Turn the command line into processes with `%wallpaper%` replaced by `image`
+    /// and `%monitor%` by `monitor`
+    fn build(
+        &self,
+        line: &CommandLine,
+        image: &Path,
+        monitor: &str,
+    ) -> Result<Vec<Command>, Error> {
+        match line {
+            CommandLine::Line(line) if self.shell => {
+                let quoted = shell_words::quote(&image.to_string_lossy()).into_owned();
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(
+                    line.replace(WALLPAPER, &quoted)
+                        .replace(MONITOR, &shell_words::quote(monitor)),
+                );
+                Ok(vec![command])
+            }
+            CommandLine::Line(line) => {
+                let argv = shell_words::split(line)
+                    .map_err(|err| Error::Command(format!("Can't parse '{line}': {err}")))?;
+                if argv.iter().any(|word| is_shell_operator(word)) {
+                    return Err(Error::Command(format!(
+                        "'{line}' uses shell syntax, set wallpaper_command_shell = true or use a list of commands"
+                    )));
+                }
+                Ok(vec![argv_command(&argv, image, monitor)?])
+            }
+            CommandLine::Argv(argv) => Ok(vec![argv_command(argv, image, monitor)?]),
+            CommandLine::Sequence(commands) => commands
+                .iter()
+                .map(|argv| argv_command(argv, image, monitor))
+                .collect(),
+        }
+    }
+}
+
+/// Build a process without a shell, substituting the placeholders in every argument
+fn argv_command(argv: &[String], image: &Path, monitor: &str) -> Result<Command, Error> {
+    let substitute = |arg: &String| -> OsString {
+        if arg == WALLPAPER {
+            // Keep paths which aren't valid UTF-8 intact
+            image.as_os_str().to_owned()
+        } else {
+            arg.replace(WALLPAPER, &image.to_string_lossy())
+                .replace(MONITOR, monitor)
+                .into()
+        }
+    };
+
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| Error::Command("Empty command".to_owned()))?;
+    let mut command = Command::new(substitute(program));
+    command.args(args.iter().map(substitute));
+    Ok(command)
+}
+
+/// Runs user supplied commands
+#[derive(Debug)]
+pub struct CommandBackend {
+    cmds: WallpaperCommands,
+}
+
+impl CommandBackend {
+    pub fn new(cmds: WallpaperCommands) -> Self {
+        CommandBackend { cmds }
+    }
+}
+
+impl Backend for CommandBackend {
+    fn apply(&mut self, job: &Job) -> Result<(), Error> {
+        let monitor = job.output.as_deref().unwrap_or_default();
+        for mut command in self
+            .cmds
+            .build(&self.cmds.wallpaper_cmd, &job.image, monitor)?
+        {
+            run(&mut command, job.timeout)?;
+        }
+
+        if let Some(delay) = self.cmds.wallpaper_post_offset {
+            if let Some(line) = &self.cmds.wallpaper_post_cmd {
+                if let Some(prev) = job.history.iter().rev().nth(delay) {
+                    for mut command in self.cmds.build(line, prev, monitor)? {
+                        run(&mut command, job.timeout)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use std::{process::Command, slice};
+
+use serde::Deserialize;
+
+use super::{run, Backend, Job};
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    pub fit: FitMode,
+    /// Don't write `~/.fehbg`
+    pub no_fehbg: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            fit: FitMode::Fill,
+            no_fehbg: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    Center,
+    Fill,
+    Max,
+    Scale,
+    Tile,
+}
+
+/// X11 wallpaper through `feh --bg-*`
+///
+/// feh can't draw on a single output. The images of one wallpaper, like the slices of a
+/// panorama, are passed to one call, which puts them on the screens in Xinerama order.
+#[derive(Debug)]
+pub struct Feh {
+    options: Options,
+}
+
+impl Feh {
+    pub fn new(options: Options) -> Self {
+        Feh { options }
+    }
+}
+
+impl Backend for Feh {
+    fn apply(&mut self, job: &Job) -> Result<(), Error> {
+        self.apply_all(slice::from_ref(job))
+    }
+
+    fn apply_all(&mut self, jobs: &[Job]) -> Result<(), Error> {
+        let Some(first) = jobs.first() else {
+            return Ok(());
+        };
+        let fit = match self.options.fit {
+            FitMode::Center => "--bg-center",
+            FitMode::Fill => "--bg-fill",
+            FitMode::Max => "--bg-max",
+            FitMode::Scale => "--bg-scale",
+            FitMode::Tile => "--bg-tile",
+        };
+        let mut command = Command::new("feh");
+        if self.options.no_fehbg {
+            command.arg("--no-fehbg");
+        }
+        command.arg(fit);
+        run(
+            command.args(jobs.iter().map(|job| &job.image)),
+            first.timeout,
+        )
+    }
+}
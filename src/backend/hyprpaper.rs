@@ -0,0 +1,70 @@
+use std::{collections::HashMap, path::PathBuf, process::Command, time::Duration};
+
+use log::warn;
+use serde::Deserialize;
+
+use super::{run, Backend, Job};
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Default)]
+#[serde(default)]
+pub struct Options {
+    /// Scale the image down to fit instead of covering the output
+    pub contain: bool,
+    /// Outputs to draw on, all outputs if empty
+    pub outputs: Vec<String>,
+}
+
+/// Wayland wallpaper through `hyprctl hyprpaper`
+///
+/// Images are preloaded before they are shown and unloaded once no output shows them anymore.
+#[derive(Debug)]
+pub struct Hyprpaper {
+    options: Options,
+    /// Image shown by every output the backend drew on, `None` for all outputs
+    loaded: HashMap<Option<String>, PathBuf>,
+}
+
+impl Hyprpaper {
+    pub fn new(options: Options) -> Self {
+        Hyprpaper {
+            options,
+            loaded: HashMap::new(),
+        }
+    }
+}
+
+fn hyprctl(args: &[&str], timeout: Duration) -> Result<(), Error> {
+    run(Command::new("hyprctl").arg("hyprpaper").args(args), timeout)
+}
+
+impl Backend for Hyprpaper {
+    fn apply(&mut self, job: &Job) -> Result<(), Error> {
+        let path = job.image.to_string_lossy();
+        hyprctl(&["preload", &path], job.timeout)?;
+
+        let image = if self.options.contain {
+            format!("contain:{path}")
+        } else {
+            path.to_string()
+        };
+        let outputs = job.outputs(&self.options.outputs);
+        if outputs.is_empty() {
+            hyprctl(&["wallpaper", &format!(",{image}")], job.timeout)?;
+        } else {
+            for output in outputs {
+                hyprctl(&["wallpaper", &format!("{output},{image}")], job.timeout)?;
+            }
+        }
+
+        let previous = self.loaded.insert(job.output.clone(), job.image.clone());
+        if let Some(previous) = previous {
+            if !self.loaded.values().any(|image| *image == previous) {
+                if let Err(err) = hyprctl(&["unload", &previous.to_string_lossy()], job.timeout) {
+                    warn!("{err}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    collections::HashMap,
+    process::{Child, Command, Stdio},
+    thread::sleep,
+    time::Duration,
+};
+
+use log::{trace, warn};
+use serde::Deserialize;
+
+use super::{Backend, Job};
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    pub fit: FitMode,
+    /// Outputs to draw on, all outputs if empty
+    pub outputs: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            fit: FitMode::Fill,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    Stretch,
+    Fit,
+    Fill,
+    Center,
+    Tile,
+}
+
+/// Wayland wallpaper through `swaybg`
+///
+/// swaybg keeps running while the wallpaper is shown, so it is restarted for every image.
+#[derive(Debug)]
+pub struct Swaybg {
+    options: Options,
+    /// Instance for every output the backend drew on, `None` for all outputs
+    running: HashMap<Option<String>, Child>,
+}
+
+impl Swaybg {
+    pub fn new(options: Options) -> Self {
+        Swaybg {
+            options,
+            running: HashMap::new(),
+        }
+    }
+}
+
+fn stop(mut child: Child) {
+    if let Err(err) = child.kill().and_then(|_| child.wait()) {
+        warn!("Couldn't stop swaybg: {err}");
+    }
+}
+
+impl Backend for Swaybg {
+    fn apply(&mut self, job: &Job) -> Result<(), Error> {
+        let image = &job.image;
+        let fit = match self.options.fit {
+            FitMode::Stretch => "stretch",
+            FitMode::Fit => "fit",
+            FitMode::Fill => "fill",
+            FitMode::Center => "center",
+            FitMode::Tile => "tile",
+        };
+
+        let mut command = Command::new("swaybg");
+        let outputs = job.outputs(&self.options.outputs);
+        if outputs.is_empty() {
+            command
+                .args(["-o", "*"])
+                .arg("-i")
+                .arg(image)
+                .args(["-m", fit]);
+        } else {
+            for output in outputs {
+                command
+                    .args(["-o", output])
+                    .arg("-i")
+                    .arg(image)
+                    .args(["-m", fit]);
+            }
+        }
+        trace!("Calling {:?}", command);
+        let mut child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| Error::Command(format!("Couldn't start swaybg: {err}")))?;
+
+        // Give the new instance time to draw before removing the old one
+        sleep(Duration::from_millis(200));
+        if let Some(status) = child.try_wait()? {
+            return Err(Error::Command(format!("swaybg exited with {status}")));
+        }
+        if let Some(previous) = self.running.insert(job.output.clone(), child) {
+            stop(previous);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Swaybg {
+    fn drop(&mut self) {
+        self.running.drain().for_each(|(_, child)| stop(child));
+    }
+}
@@ -0,0 +1,80 @@
+use std::process::Command;
+
+use serde::Deserialize;
+
+use super::{run, Backend, Job};
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    pub resize: Resize,
+    /// Passed to `--transition-type`, for example `simple`, `grow` or `wipe`
+    pub transition_type: String,
+    /// Length of the transition in seconds
+    pub transition_duration: Option<f32>,
+    pub transition_fps: Option<u32>,
+    /// Outputs to draw on, all outputs if empty
+    pub outputs: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            resize: Resize::Crop,
+            transition_type: "simple".to_owned(),
+            transition_duration: None,
+            transition_fps: None,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resize {
+    Crop,
+    Fit,
+    No,
+}
+
+/// Wayland wallpaper through the `swww` daemon
+#[derive(Debug)]
+pub struct Swww {
+    options: Options,
+}
+
+impl Swww {
+    pub fn new(options: Options) -> Self {
+        Swww { options }
+    }
+}
+
+impl Backend for Swww {
+    fn apply(&mut self, job: &Job) -> Result<(), Error> {
+        let image = &job.image;
+        let resize = match self.options.resize {
+            Resize::Crop => "crop",
+            Resize::Fit => "fit",
+            Resize::No => "no",
+        };
+
+        let mut command = Command::new("swww");
+        command
+            .arg("img")
+            .arg(image)
+            .args(["--resize", resize])
+            .args(["--transition-type", &self.options.transition_type]);
+        if let Some(duration) = self.options.transition_duration {
+            command.args(["--transition-duration", &duration.to_string()]);
+        }
+        if let Some(fps) = self.options.transition_fps {
+            command.args(["--transition-fps", &fps.to_string()]);
+        }
+        let outputs = job.outputs(&self.options.outputs);
+        if !outputs.is_empty() {
+            command.args(["--outputs", &outputs.join(",")]);
+        }
+        run(&mut command, job.timeout)
+    }
+}
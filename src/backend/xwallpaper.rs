@@ -0,0 +1,69 @@
+use std::process::Command;
+
+use serde::Deserialize;
+
+use super::{run, Backend, Job};
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    pub fit: FitMode,
+    /// Outputs to draw on, all outputs if empty
+    pub outputs: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            fit: FitMode::Zoom,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    Center,
+    Maximize,
+    Stretch,
+    Tile,
+    Zoom,
+}
+
+/// X11 wallpaper through `xwallpaper`
+#[derive(Debug)]
+pub struct Xwallpaper {
+    options: Options,
+}
+
+impl Xwallpaper {
+    pub fn new(options: Options) -> Self {
+        Xwallpaper { options }
+    }
+}
+
+impl Backend for Xwallpaper {
+    fn apply(&mut self, job: &Job) -> Result<(), Error> {
+        let image = &job.image;
+        let fit = match self.options.fit {
+            FitMode::Center => "--center",
+            FitMode::Maximize => "--maximize",
+            FitMode::Stretch => "--stretch",
+            FitMode::Tile => "--tile",
+            FitMode::Zoom => "--zoom",
+        };
+
+        let mut command = Command::new("xwallpaper");
+        let outputs = job.outputs(&self.options.outputs);
+        if outputs.is_empty() {
+            command.args(["--output", "all", fit]).arg(image);
+        } else {
+            for output in outputs {
+                command.args(["--output", output, fit]).arg(image);
+            }
+        }
+        run(&mut command, job.timeout)
+    }
+}
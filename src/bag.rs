@@ -0,0 +1,140 @@
+//! Shuffle mode, which shows every image once in random order before starting over
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use rand::seq::SliceRandom;
+
+#[derive(Debug, Default)]
+pub struct ShuffleBag {
+    /// Images left in this round, the next one last
+    remaining: Vec<PathBuf>,
+    /// The same for quick lookups
+    in_bag: HashSet<PathBuf>,
+    /// Images shown in this round
+    drawn: HashSet<PathBuf>,
+}
+
+impl ShuffleBag {
+    /// Continue a round with the images which were left, the other candidates count as shown
+    pub fn restore(remaining: Vec<PathBuf>, candidates: &[&PathBuf]) -> Self {
+        let in_bag: HashSet<PathBuf> = remaining.iter().cloned().collect();
+        let drawn = if remaining.is_empty() {
+            HashSet::new()
+        } else {
+            candidates
+                .iter()
+                .filter(|image| !in_bag.contains(**image))
+                .map(|image| (*image).clone())
+                .collect()
+        };
+        ShuffleBag {
+            remaining,
+            in_bag,
+            drawn,
+        }
+    }
+
+    pub fn remaining(&self) -> &[PathBuf] {
+        &self.remaining
+    }
+
+    /// The next image of the round, starting a new round once every candidate was shown
+    ///
+    /// Candidates which are gone are dropped and new ones are shuffled into the bag.
+    pub fn draw(&mut self, candidates: &[&PathBuf], current: &Path) -> Option<PathBuf> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let known: HashSet<&PathBuf> = candidates.iter().copied().collect();
+        self.remaining.retain(|image| known.contains(image));
+        self.in_bag.retain(|image| known.contains(image));
+        self.drawn.retain(|image| known.contains(image));
+
+        let new: Vec<PathBuf> = candidates
+            .iter()
+            .filter(|image| !self.in_bag.contains(**image) && !self.drawn.contains(**image))
+            .map(|image| (*image).clone())
+            .collect();
+        if !new.is_empty() {
+            self.in_bag.extend(new.iter().cloned());
+            self.remaining.extend(new);
+            self.remaining.shuffle(&mut rng);
+        }
+
+        if self.remaining.is_empty() {
+            debug!(
+                "Every image was shown, shuffling {} images",
+                candidates.len()
+            );
+            self.drawn.clear();
+            self.remaining = candidates.iter().map(|image| (*image).clone()).collect();
+            self.in_bag = self.remaining.iter().cloned().collect();
+            self.remaining.shuffle(&mut rng);
+            // A new round doesn't start with the image shown last
+            let last = self.remaining.len() - 1;
+            if last > 0 && self.remaining[last] == current {
+                self.remaining.swap(0, last);
+            }
+        }
+
+        let image = self.remaining.pop()?;
+        self.in_bag.remove(&image);
+        self.drawn.insert(image.clone());
+        Some(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn images(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn every_image_once_per_round() {
+        let images = images(&["a", "b", "c", "d"]);
+        let candidates: Vec<&PathBuf> = images.iter().collect();
+        let mut bag = ShuffleBag::default();
+        let mut current = PathBuf::from("a");
+        for _ in 0..3 {
+            let mut round = HashSet::new();
+            for _ in 0..images.len() {
+                current = bag.draw(&candidates, &current).unwrap();
+                round.insert(current.clone());
+            }
+            assert_eq!(round.len(), images.len());
+        }
+    }
+
+    #[test]
+    fn restored_round_continues() {
+        let images = images(&["a", "b", "c", "d"]);
+        let candidates: Vec<&PathBuf> = images.iter().collect();
+        let mut bag = ShuffleBag::restore(vec![PathBuf::from("c")], &candidates);
+        assert_eq!(
+            bag.draw(&candidates, Path::new("a")),
+            Some(PathBuf::from("c"))
+        );
+        assert!(bag.remaining().is_empty());
+    }
+
+    #[test]
+    fn new_images_join_the_round() {
+        let images = images(&["a", "b", "c"]);
+        let mut bag = ShuffleBag::default();
+        let first = bag.draw(&[&images[0], &images[1]], Path::new("x")).unwrap();
+        let candidates: Vec<&PathBuf> = images.iter().collect();
+        let mut rest: Vec<PathBuf> = (0..2)
+            .map(|_| bag.draw(&candidates, Path::new("x")).unwrap())
+            .collect();
+        rest.push(first);
+        rest.sort();
+        assert_eq!(rest, images);
+    }
+}
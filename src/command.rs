@@ -1,8 +1,13 @@
-use std::{fmt::{format, Display}, path::PathBuf, time::Duration};
+use std::{path::PathBuf, time::Duration};
 
 use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
 
 use crate::daemon::DaemonArgs;
+use crate::library::List;
+use crate::protocol::Request;
+use crate::ratings::MAX_STARS;
+use crate::state::NextImage;
 
 #[derive(Subcommand, PartialEq, Eq)]
 pub enum Command {
@@ -18,13 +23,41 @@ pub enum Command {
     /// Display the fallback wallpaper
     /// If called again displays the previous image
     Fallback,
-    /// Change the directory from which images are sourced
+    /// Change the directory or playlist from which images are sourced, replacing the configured sources
     WpDir(WallpaperDirectory),
+    /// Enable or disable a source
+    #[clap(subcommand)]
+    Source(SourceArgs),
+    /// Write images to a playlist file
+    #[clap(subcommand)]
+    Playlist(PlaylistArgs),
+    /// Rate the current image with 1 to 5 stars
+    Rate(Stars),
+    /// Add the current image to the favorites
+    Favorite,
+    /// Never show the current image again
+    Ban,
+    /// List or remove favorites
+    #[clap(subcommand)]
+    Favorites(ListArgs),
+    /// List or remove banned images
+    #[clap(subcommand)]
+    Banned(ListArgs),
     /// Set the interval for new images in seconds
     Interval(IntervalDuration),
     /// Query information about the current state
     #[clap(subcommand)]
     Get(GetArgs),
+    /// Print an event as JSON line every time the state changes
+    Watch,
+    /// Show the whole state at once
+    Status(StatusArgs),
+    /// Show which images were shown and skipped most, and which never were
+    Stats(StatsArgs),
+    /// Read the config file again and apply the changes
+    Reload,
+    /// Look for connected monitors and list them
+    Monitors,
     Daemon(DaemonArgs),
 }
 
@@ -34,15 +67,76 @@ pub struct IntervalDuration {
     pub duration: Duration,
 }
 
+#[derive(Args, PartialEq, Eq)]
+pub struct Stars {
+    #[clap(parse(try_from_str = parse_stars))]
+    pub stars: u8,
+}
+
+#[derive(Args, PartialEq, Eq, Clone, Default)]
+pub struct StatusArgs {
+    /// Print the status as JSON
+    #[clap(long, conflicts_with = "format")]
+    pub json: bool,
+    /// Template where {output}, {wallpaper}, {name}, {mode}, {duration}, {remaining},
+    /// {fallback}, {wp_dir}, {images}, {history} and {rating} get replaced with their values
+    #[clap(long)]
+    pub format: Option<String>,
+}
+
+#[derive(Args, PartialEq, Eq, Clone, Default)]
+pub struct StatsArgs {
+    /// Number of images in every list
+    #[clap(long, default_value_t = 10)]
+    pub top: usize,
+    /// Print the statistics as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
 #[derive(Args, PartialEq, Eq)]
 pub struct WallpaperDirectory {
     pub path: PathBuf,
 }
 
+#[derive(Subcommand, PartialEq, Eq)]
+pub enum SourceArgs {
+    Enable { name: String },
+    Disable { name: String },
+}
+
+#[derive(Subcommand, PartialEq, Eq)]
+pub enum PlaylistArgs {
+    /// Save the candidate images, in the order linear mode shows them
+    Save {
+        file: PathBuf,
+        /// Save the history instead, oldest image first
+        #[clap(long)]
+        history: bool,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Eq)]
+pub enum ListArgs {
+    List,
+    /// Remove an image, the current one if no path is given
+    Remove(Image),
+}
+
 #[derive(Subcommand, PartialEq, Eq)]
 pub enum ModeArgs {
     Linear,
     Random,
+    /// Show every image once in random order before repeating any
+    Shuffle,
+    /// Let `selector_command` choose the images
+    External,
+    /// Like random, but choose images with more stars more often
+    Weighted,
+    /// Show every favorite once in random order before repeating any
+    Favorites,
+    /// Show the images which weren't shown for the longest time
+    LeastRecent,
     Static(Image),
 }
 
@@ -51,13 +145,18 @@ pub struct Image {
     pub path: Option<PathBuf>,
 }
 
-#[derive(Subcommand, PartialEq, Eq)]
+#[derive(Subcommand, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum GetArgs {
     Wallpaper,
     Duration,
     Mode,
     Fallback,
     WpDir,
+    /// Every source with its weight and number of images
+    Sources,
+    /// Stars of the current image
+    Rating,
 }
 
 fn parse_duration(arg: &str) -> Result<std::time::Duration, std::num::ParseIntError> {
@@ -65,37 +164,118 @@ fn parse_duration(arg: &str) -> Result<std::time::Duration, std::num::ParseIntEr
     Ok(std::time::Duration::from_secs(seconds))
 }
 
-impl Display for Command {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let args = match self {
-            Command::Next => "next".to_string(),
-            Command::Stop => "stop".to_string(),
-            Command::Previous => "previous".to_string(),
+fn parse_stars(arg: &str) -> Result<u8, String> {
+    match arg.parse() {
+        Ok(stars) if (1..=MAX_STARS).contains(&stars) => Ok(stars),
+        _ => Err(format!("expected 1 to {MAX_STARS} stars")),
+    }
+}
+
+impl Command {
+    /// Convert the command into a request for the daemon
+    /// Returns `None` for commands that aren't sent to the daemon
+    pub fn into_request(self) -> Option<Request> {
+        let request = match self {
+            Command::Next => Request::Next,
+            Command::Stop => Request::Stop,
+            Command::Previous => Request::Previous,
             Command::Mode(mode) => match mode {
-                ModeArgs::Linear => "mode linear".to_string(),
-                ModeArgs::Random => "mode random".to_string(),
-                ModeArgs::Static(img) => {
-                    if let Some(path) = &img.path {
-                        format!("mode static {}", path.to_string_lossy())
-                    } else {
-                        "mode static".to_string()
-                    }
-                }
+                ModeArgs::Linear => Request::Mode {
+                    mode: NextImage::Linear,
+                    image: None,
+                },
+                ModeArgs::Random => Request::Mode {
+                    mode: NextImage::Random,
+                    image: None,
+                },
+                ModeArgs::Shuffle => Request::Mode {
+                    mode: NextImage::Shuffle,
+                    image: None,
+                },
+                ModeArgs::External => Request::Mode {
+                    mode: NextImage::External,
+                    image: None,
+                },
+                ModeArgs::Weighted => Request::Mode {
+                    mode: NextImage::Weighted,
+                    image: None,
+                },
+                ModeArgs::Favorites => Request::Mode {
+                    mode: NextImage::Favorites,
+                    image: None,
+                },
+                ModeArgs::LeastRecent => Request::Mode {
+                    mode: NextImage::LeastRecent,
+                    image: None,
+                },
+                ModeArgs::Static(img) => Request::Mode {
+                    mode: NextImage::Static,
+                    image: img.path.map(absolute_path),
+                },
+            },
+            Command::Fallback => Request::Fallback,
+            Command::Rate(rate) => Request::Rate { stars: rate.stars },
+            Command::Favorite => Request::Mark {
+                list: List::Favorites,
+                image: None,
+                marked: true,
+            },
+            Command::Ban => Request::Mark {
+                list: List::Banned,
+                image: None,
+                marked: true,
+            },
+            Command::Favorites(args) => args.into_request(List::Favorites),
+            Command::Banned(args) => args.into_request(List::Banned),
+            Command::Interval(dur) => Request::Interval {
+                seconds: dur.duration.as_secs(),
+            },
+            Command::Get(what) => Request::Get { what },
+            Command::Watch => Request::Watch,
+            Command::Status(_) => Request::Status,
+            Command::Stats(args) => Request::Stats { top: args.top },
+            Command::Reload => Request::Reload,
+            Command::Monitors => Request::Monitors,
+            Command::Daemon(_) => return None,
+            Command::Source(SourceArgs::Enable { name }) => Request::Source {
+                name,
+                enabled: true,
             },
-            Command::Fallback => "fallback".to_string(),
-            Command::Interval(dur) => format!("interval {}", dur.duration.as_secs()),
-            Command::Get(what) => match what {
-                GetArgs::Wallpaper => "get wallpaper".to_string(),
-                GetArgs::Duration => "get duration".to_string(),
-                GetArgs::Mode => "get mode".to_string(),
-                GetArgs::Fallback => "get fallback".to_string(),
-                GetArgs::WpDir => "get wp-dir".to_string(),
+            Command::Source(SourceArgs::Disable { name }) => Request::Source {
+                name,
+                enabled: false,
+            },
+            Command::Playlist(PlaylistArgs::Save { file, history }) => Request::SavePlaylist {
+                path: absolute_path(file),
+                history,
+            },
+            Command::WpDir(wallpaper_directory) => Request::WpDir {
+                path: absolute_path(wallpaper_directory.path),
             },
-            Command::Daemon(_) => "daemon".to_string(),
-            Command::WpDir(wallpaper_directory) => {
-                format!("wp-dir {}", wallpaper_directory.path.to_str().unwrap().to_owned())
-            }
         };
-        write!(f, "{args}")
+        Some(request)
+    }
+}
+
+impl ListArgs {
+    fn into_request(self, list: List) -> Request {
+        match self {
+            ListArgs::List => Request::List { list },
+            ListArgs::Remove(image) => Request::Mark {
+                list,
+                image: image.path.map(absolute_path),
+                marked: false,
+            },
+        }
+    }
+}
+
+/// The daemon doesn't share the working directory of the client
+fn absolute_path(path: PathBuf) -> PathBuf {
+    if path.is_relative() && !path.starts_with("~") {
+        if let Ok(cwd) = std::env::current_dir() {
+            return cwd.join(path);
+        }
     }
+    path
 }
@@ -0,0 +1,269 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::backend::{BackendConfig, CommandLine};
+use crate::daemon::DaemonArgs;
+use crate::error::Error;
+use crate::index::IndexOptions;
+use crate::monitors::{Monitor, Transform};
+use crate::order::Order;
+use crate::scan::ScanConfig;
+use crate::sets::SetsConfig;
+use crate::sources::{SourceConfig, DEFAULT_SOURCE};
+use crate::state::{AspectRatio, NextImage};
+
+/// Settings read from `wallpaperd.toml`
+///
+/// Every key is optional, missing keys fall back to [`Config::default`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Image to show by default
+    pub default_image: PathBuf,
+    /// Directory to search for images, or a playlist listing them
+    pub wallpaper_directory: PathBuf,
+    /// Which files in the wallpaper directory are images
+    pub scan: ScanConfig,
+    /// Directories to choose images from, by name, only `wallpaper_directory` if empty
+    pub sources: BTreeMap<String, SourceConfig>,
+    /// Time in seconds between wallpaper changes
+    pub interval: u64,
+    /// Maximum size of the history (used for getting the previous wallpaper)
+    pub history_length: usize,
+    pub mode: NextImage,
+    /// How linear mode orders the images of a directory
+    pub order: Order,
+    pub order_descending: bool,
+    /// Linear mode turns around at the first and last image instead of starting over
+    pub ping_pong: bool,
+    /// Weight of images without rating in weighted mode, rated images weigh their stars
+    pub unrated_weight: f64,
+    /// Program choosing the next image in external mode, gets the candidates as JSON on stdin
+    pub selector_command: Option<CommandLine>,
+    /// Whether to choose images with the aspect ratio of the output
+    pub aspect_ratio: AspectRatio,
+    /// How much the aspect ratio of an image may differ, 0.1 allows 10%
+    pub aspect_ratio_tolerance: f64,
+    /// Program which sets the wallpaper
+    pub backend: BackendConfig,
+    /// Command to call to change the wallpaper when using the command backend
+    /// Either a string, a list of arguments or a list of commands
+    /// %wallpaper% gets replaced with the path to the wallpaper
+    pub wallpaper_change_command: CommandLine,
+    /// Command to call after changing the wallpaper
+    /// %wallpaper% gets replaced with the path to the wallpaper
+    pub wallpaper_post_change_command: Option<CommandLine>,
+    /// How many cycles of delay to keep
+    pub wallpaper_post_change_offset: Option<usize>,
+    /// Run string commands through 'sh -c' with the path quoted
+    pub wallpaper_command_shell: bool,
+    /// Seconds a wallpaper command may run before it is killed
+    pub command_timeout: u64,
+    /// Reload the config when the file changes
+    pub watch_config: bool,
+    /// Command printing the connected monitors as JSON, like `hyprctl monitors -j` or `wlr-randr --json`
+    pub monitor_command: Option<CommandLine>,
+    /// Seconds between runs of the monitor command
+    pub monitor_poll_interval: u64,
+    /// Cut every image into one slice per monitor, only without `outputs`
+    pub span: bool,
+    /// Position and size of the monitors, used for spanning until they were discovered
+    pub layout: BTreeMap<String, LayoutConfig>,
+    /// Show matching images on the outputs together, only without `outputs`
+    pub sets: Option<SetsConfig>,
+    /// Outputs with their own rotation, a single rotation for all outputs if empty
+    pub outputs: BTreeMap<String, OutputConfig>,
+}
+
+/// An `[outputs.<name>]` table, keys which are missing use the top level value
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub default_image: Option<PathBuf>,
+    pub wallpaper_directory: Option<PathBuf>,
+    pub sources: Option<BTreeMap<String, SourceConfig>>,
+    pub interval: Option<u64>,
+    pub history_length: Option<usize>,
+    pub mode: Option<NextImage>,
+    pub aspect_ratio: Option<AspectRatio>,
+}
+
+/// A `[layout.<name>]` table, in pixels
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LayoutConfig {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_image: PathBuf::from_str("~/Pictures/wallpaper.png").unwrap(),
+            wallpaper_directory: PathBuf::from_str("~/Pictures/wallpapers/").unwrap(),
+            scan: ScanConfig::default(),
+            sources: BTreeMap::new(),
+            interval: 60,
+            history_length: 25,
+            mode: NextImage::Random,
+            order: Order::Name,
+            order_descending: false,
+            ping_pong: false,
+            unrated_weight: 3.0,
+            selector_command: None,
+            aspect_ratio: AspectRatio::Ignore,
+            aspect_ratio_tolerance: 0.1,
+            backend: BackendConfig::default(),
+            wallpaper_change_command: CommandLine::Line("feh -r %wallpaper%".to_owned()),
+            wallpaper_post_change_command: None,
+            wallpaper_post_change_offset: None,
+            wallpaper_command_shell: false,
+            command_timeout: 10,
+            watch_config: false,
+            monitor_command: None,
+            monitor_poll_interval: 5,
+            span: false,
+            layout: BTreeMap::new(),
+            sets: None,
+            outputs: BTreeMap::new(),
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = toml::de::Error;
+
+    /// Parse the config, warning about every key that isn't used
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let deserializer = toml::de::Deserializer::new(s);
+        serde_ignored::deserialize(deserializer, |path| warn_unknown("", &path))
+    }
+}
+
+/// Warn about a key of the config file which isn't used, `path` is relative to the table `prefix`
+pub fn warn_unknown(prefix: &str, path: &serde_ignored::Path) {
+    warn!("Unknown key in config file: {}", key(prefix, path));
+}
+
+/// `path` the way it is written in the config file
+fn key(prefix: &str, path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+
+    let join = |parent: &Path, child: &str| match key(prefix, parent) {
+        parent if parent.is_empty() => child.to_owned(),
+        parent => format!("{parent}.{child}"),
+    };
+    match path {
+        Path::Root => prefix.to_owned(),
+        Path::Seq { parent, index } => join(parent, &index.to_string()),
+        Path::Map { parent, key } => join(parent, key),
+        // Not part of the key
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => key(prefix, parent),
+    }
+}
+
+impl Config {
+    /// Read the config file, a missing file results in the default config
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if !path.is_file() {
+            return Ok(Config::default());
+        }
+        let config = fs::read_to_string(path)?;
+        Config::from_str(&config)
+            .map_err(|err| Error::Config(format!("{}: {err}", path.to_string_lossy())))
+    }
+
+    /// Names of the configured outputs, `None` is the rotation shared by all outputs
+    pub fn output_names(&self) -> Vec<Option<String>> {
+        if self.outputs.is_empty() {
+            vec![None]
+        } else {
+            self.outputs.keys().cloned().map(Some).collect()
+        }
+    }
+
+    /// The config with the settings of the output applied
+    pub fn for_output(&self, name: Option<&str>) -> Config {
+        let mut config = self.clone();
+        if let Some(output) = name.and_then(|name| self.outputs.get(name)) {
+            let output = output.clone();
+            config.default_image = output.default_image.unwrap_or(config.default_image);
+            // A directory of the output replaces the sources of the top level
+            if let Some(dir) = output.wallpaper_directory {
+                config.wallpaper_directory = dir;
+                config.sources.clear();
+            }
+            config.sources = output.sources.unwrap_or(config.sources);
+            config.interval = output.interval.unwrap_or(config.interval);
+            config.history_length = output.history_length.unwrap_or(config.history_length);
+            config.mode = output.mode.unwrap_or(config.mode);
+            config.aspect_ratio = output.aspect_ratio.unwrap_or(config.aspect_ratio);
+        }
+        config
+    }
+
+    /// Name and settings of every source, `wallpaper_directory` is the only source if none are configured
+    pub fn sources(&self) -> Vec<(String, SourceConfig)> {
+        if self.sources.is_empty() {
+            vec![(
+                DEFAULT_SOURCE.to_owned(),
+                SourceConfig::new(self.wallpaper_directory.clone()),
+            )]
+        } else {
+            self.sources.clone().into_iter().collect()
+        }
+    }
+
+    /// What the image index of every source considers an image, and how it is ordered
+    pub fn index_options(&self) -> IndexOptions {
+        IndexOptions {
+            scan: self.scan.clone(),
+            sets: self.sets.clone(),
+            order: self.order,
+            descending: self.order_descending,
+        }
+    }
+
+    /// The monitors of the `[layout]` tables
+    pub fn layout(&self) -> Vec<Monitor> {
+        self.layout
+            .iter()
+            .map(|(name, layout)| Monitor {
+                name: name.clone(),
+                width: layout.width,
+                height: layout.height,
+                x: layout.x,
+                y: layout.y,
+                scale: 1.0,
+                transform: Transform::Normal,
+            })
+            .collect()
+    }
+
+    /// The config with the settings given on the command line applied, they take precedence
+    pub fn with_args(mut self, args: &DaemonArgs) -> Config {
+        if let Some(default) = &args.default {
+            self.default_image = default.clone();
+        }
+        if let Some(dir) = &args.wallpaper_directory {
+            self.wallpaper_directory = dir.clone();
+            self.sources.clear();
+        }
+        if let Some(interval) = args.interval {
+            self.interval = interval.as_secs();
+        }
+        self.history_length = args.history_length.unwrap_or(self.history_length);
+        self.mode = args.mode.unwrap_or(self.mode);
+        self
+    }
+}
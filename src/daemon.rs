@@ -1,19 +1,35 @@
+use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::Write;
 use std::os::unix::net::*;
 use std::os::unix::prelude::{FromRawFd, RawFd};
 use std::path::PathBuf;
 use std::process::exit;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread::{self, sleep};
 use std::time::Duration;
 
 use clap::Parser;
+use inotify::Inotify;
 use log::{debug, error, info};
 
+use crate::backend;
+use crate::config::Config;
+use crate::error::Error;
+use crate::index::watch_images;
+use crate::library::{self, Library};
+use crate::monitors;
+use crate::outputs::{Output, Outputs};
+use crate::playlist;
+use crate::protocol::{
+    self, Envelope, ErrorCode, Event, Hello, Request, Response, PROTOCOL_VERSION,
+};
+use crate::reload::{self, ConfigReloader};
+use crate::select::SelectorQuery;
 use crate::state::*;
-//TODO: error handling
 
 /// Struct to hold and parse cli arguments
 #[derive(Parser, Debug, PartialEq, Eq)]
@@ -23,134 +39,53 @@ pub struct DaemonArgs {
     config: Option<PathBuf>,
     /// Image to show by default
     #[clap(short, long, value_parser, value_name = "FILE")]
-    default: Option<PathBuf>,
+    pub default: Option<PathBuf>,
     /// Socket for communication
     #[clap(short, long, value_parser, value_name = "FILE")]
     socket: Option<PathBuf>,
-    /// Directory to search for images
+    /// Directory to search for images, or a playlist listing them
     #[clap(short, long, value_parser, value_name = "DIRECTORY")]
-    wallpaper_directory: Option<PathBuf>,
+    pub wallpaper_directory: Option<PathBuf>,
     /// Time in seconds between wallpaper changes
-    #[clap(short, long, parse(try_from_str = parse_duration), default_value = "60")]
-    interval: Duration,
+    #[clap(short, long, parse(try_from_str = parse_duration))]
+    pub interval: Option<Duration>,
     /// File descriptor to write to to signal readiness
     #[clap(long)]
     fd: Option<RawFd>,
     /// Maximum size of the history (used for getting the previous wallpaper)
     #[clap(long)]
-    history_length: Option<usize>,
+    pub history_length: Option<usize>,
     #[clap(short, long, arg_enum)]
-    mode: Option<NextImage>,
+    pub mode: Option<NextImage>,
     /// Command to call to change the wallpaper
-    /// calls 'sh -c ${wallpaper_change_command}'
+    /// split into arguments without a shell, unless --wallpaper-command-shell is given
     /// %wallpaper% gets replaced with the path to the wallpaper
     #[clap(long)]
     pub wallpaper_change_command: Option<String>,
     /// Command to call after changing the wallpaper
-    /// calls 'sh -c ${wallpaper_post_change_command}'
+    /// split into arguments without a shell, unless --wallpaper-command-shell is given
     /// %wallpaper% gets replaced with the path to the wallpaper
     #[clap(long)]
     pub wallpaper_post_change_command: Option<String>,
     /// How many cycles of delay to keep
     #[clap(long)]
     pub wallpaper_post_change_offset: Option<usize>,
+    /// Run the wallpaper commands through 'sh -c ${command}' with the path quoted
+    #[clap(long)]
+    pub wallpaper_command_shell: bool,
+    /// Don't restore the state of the previous run
+    #[clap(long)]
+    fresh: bool,
 }
 
-pub struct Config {
-    /// Image to show by default
-    default_image: PathBuf,
-    /// Directory to search for images
-    wallpaper_directory: PathBuf,
-    /// Time in seconds between wallpaper changes
-    interval: u64,
-    /// Maximum size of the history (used for getting the previous wallpaper)
-    history_length: usize,
-    mode: NextImage,
-    /// Command to call to change the wallpaper
-    /// calls 'sh -c ${wallpaper_change_command}'
-    /// %wallpaper% gets replaced with the path to the wallpaper
-    pub wallpaper_change_command: String,
-    /// Command to call after changing the wallpaper
-    /// calls 'sh -c ${wallpaper_post_change_command}'
-    /// %wallpaper% gets replaced with the path to the wallpaper
-    pub wallpaper_post_change_command: Option<String>,
-    /// How many cycles of delay to keep
-    pub wallpaper_post_change_offset: Option<usize>,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            default_image: PathBuf::from_str("~/Pictures/wallpaper.png").unwrap(),
-            wallpaper_directory: PathBuf::from_str("~/Pictures/wallpapers/").unwrap(),
-            interval: 60,
-            history_length: 25,
-            mode: NextImage::Random,
-            wallpaper_change_command: "feh -r %wallpaper%".to_owned(),
-            wallpaper_post_change_command: None,
-            wallpaper_post_change_offset: None,
-        }
-    }
-}
-
-impl FromStr for Config {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut config = Config::default();
-        for line in s.lines() {
-            let split: Vec<&str> = line.split("=").collect();
-            let left = split[0].trim();
-            let right = split[1]
-                .trim()
-                .trim_start_matches('"')
-                .trim_end_matches('"')
-                .trim();
-            match left {
-                "default_image" => config.default_image = right.into(),
-                "wallpaper_directory" => config.wallpaper_directory = right.into(),
-                "interval" => {
-                    if let Ok(seconds) = u64::from_str(right) {
-                        config.interval = seconds;
-                    } else {
-                        error!("Couldn't parse interval in config file");
-                    }
-                }
-                "history_length" => {
-                    if let Ok(length) = usize::from_str(right) {
-                        config.history_length = length;
-                    } else {
-                        error!("Couldn't parse history_length in config file");
-                    }
-                }
-                "mode" => {
-                    config.mode = match right {
-                        "Random" => NextImage::Random,
-                        "Linear" => NextImage::Linear,
-                        "Static" => NextImage::Static,
-                        _ => {
-                            error!("Unknown wallpaper mode: {right}");
-                            config.mode
-                        }
-                    }
-                }
-                "wallpaper_change_command" => config.wallpaper_change_command = right.into(),
-                "wallpaper_post_change_command" => {
-                    config.wallpaper_post_change_command = Some(right.into())
-                }
-                "wallpaper_post_change_offset" => {
-                    if let Ok(offset) = usize::from_str(right) {
-                        config.wallpaper_post_change_offset = Some(offset);
-                    } else {
-                        error!("Couldn't parse wallpaper_post_change_offset");
-                    }
-                }
-                _ => {
-                    error!("{left} isn't a valid key");
-                }
-            }
+impl DaemonArgs {
+    /// Settings given on the command line, which the saved state doesn't override
+    fn pinned(&self) -> Pinned {
+        Pinned {
+            interval: self.interval.is_some(),
+            mode: self.mode.is_some(),
+            image_dir: self.wallpaper_directory.is_some(),
         }
-        Ok(config)
     }
 }
 
@@ -175,16 +110,14 @@ impl Drop for UnixSocketWithDrop {
 pub fn start_daemon(args: DaemonArgs) {
     let config_file = get_config_file(&args);
 
-    let config = if config_file.is_file() {
-        let config = fs::read_to_string(config_file).expect("Couldn't read config file");
-        // Can't fail
-        Config::from_str(&config).unwrap()
-    } else {
-        Config::default()
+    let config = match Config::load(&config_file) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{err}");
+            exit(1);
+        }
     };
 
-    let wallpaper_cmds = WallpaperCommands::new(&args, &config);
-
     let socket = get_socket(&args);
     info!("Binding socket {:?}", socket);
 
@@ -199,54 +132,133 @@ pub fn start_daemon(args: DaemonArgs) {
 
     let incoming = socket.socket.incoming();
 
-    let data = Arc::new(Mutex::new(State::new(
-        args.interval,
-        args.wallpaper_directory
-            .unwrap_or(config.wallpaper_directory),
-        args.default.unwrap_or(config.default_image),
-        args.mode.unwrap_or(config.mode),
-        wallpaper_cmds,
-        args.history_length.unwrap_or(config.history_length),
-    )));
-
-    if args.fd.is_some() {
-        let mut file = unsafe { File::from_raw_fd(args.fd.unwrap()) };
+    let library = Arc::new(Mutex::new(Library::load(&state_dir())));
+    let outputs = Arc::new(Outputs::new(
+        config
+            .output_names()
+            .into_iter()
+            .map(|name| create_output(&args, &config, name, library.clone()))
+            .collect(),
+    ));
+
+    // Rated images may have been renamed while the daemon wasn't running
+    let images: HashSet<PathBuf> = outputs
+        .iter()
+        .flat_map(|output| output.state.lock().unwrap().playlist(false))
+        .collect();
+    let rated = library.clone();
+    thread::spawn(move || library::find_rated(&rated, images));
+
+    if let Some(fd) = args.fd {
+        let mut file = unsafe { File::from_raw_fd(fd) };
         writeln!(&mut file).unwrap();
     }
 
-    let d = data.clone();
-    thread::spawn(move || change_interval(d));
+    for output in outputs.iter() {
+        let state = output.state.clone();
+        thread::spawn(move || change_interval(state));
 
+        match Inotify::init() {
+            Ok(inotify) => {
+                output.state.lock().unwrap().watch_images(inotify.watches());
+                let state = output.state.clone();
+                thread::spawn(move || watch_images(inotify, state));
+            }
+            Err(err) => error!("Couldn't watch the wallpaper directory: {err}"),
+        }
+    }
+
+    let reloader = Arc::new(Mutex::new(ConfigReloader::new(args, config_file, config)));
+    reload::start_threads(&reloader, &outputs);
+
+    // Connections are handled concurrently, so a slow wallpaper command doesn't block queries
+    let stop = Arc::new(AtomicBool::new(false));
     for stream in incoming {
-        let d = data.clone();
-        let handle = thread::spawn(move || handle_connection(stream.unwrap(), d));
-        if let Ok(res) = handle.join() {
-            if res {
-                break;
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                error!("Couldn't accept connection: {err}");
+                continue;
             }
+        };
+        let (r, o, stop, path) = (
+            reloader.clone(),
+            outputs.clone(),
+            stop.clone(),
+            socket.path.clone(),
+        );
+        thread::spawn(move || {
+            if handle_connection(stream, &o, &r) {
+                stop.store(true, Ordering::SeqCst);
+                // Wake up the accept loop
+                let _ = UnixStream::connect(path);
+            }
+        });
+    }
+}
+
+/// Create the state of an output and restore it from the last run
+fn create_output(
+    args: &DaemonArgs,
+    config: &Config,
+    name: Option<String>,
+    library: Arc<Mutex<Library>>,
+) -> Output {
+    let config = config.for_output(name.as_deref()).with_args(args);
+    let backend = backend::create(args, &config);
+    let mut state = State::new(&config, backend, name.clone(), library);
+
+    let state_file = get_state_file(name.as_deref());
+    if args.fresh && state_file.is_file() && fs::remove_file(&state_file).is_err() {
+        error!("Couldn't delete the saved state");
+    }
+    match state.restore(state_file, args.pinned()) {
+        Ok(true) => {
+            // Errors are logged by the worker
+            state.update();
         }
+        Ok(false) => {}
+        Err(err) => error!("Couldn't restore the saved state: {err}"),
+    }
+    Output {
+        name,
+        state: Arc::new(Mutex::new(state)),
     }
 }
 
 fn get_config_file(args: &DaemonArgs) -> PathBuf {
     args.config.as_ref().map_or_else(
-        || {
-            let mut dotconfig = std::env::var("XDG_CONFIG_HOME")
-                .map(PathBuf::from)
-                .unwrap_or_else(|_arg| {
-                    let mut home = PathBuf::from(std::env::var("HOME").unwrap());
-                    home.push(".config");
-                    home
-                });
-
-            dotconfig.push("wallpaperd");
-            dotconfig.push("wallpaperd.toml");
-            dotconfig
-        },
+        || xdg_dir("XDG_CONFIG_HOME", ".config").join("wallpaperd.toml"),
         |val| val.to_owned(),
     )
 }
 
+/// The wallpaperd directory in the XDG base directory `var`, or in `fallback` in the home directory
+pub fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    std::env::var(var)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(std::env::var("HOME").unwrap()).join(fallback))
+        .join("wallpaperd")
+}
+
+/// Where the daemon keeps everything that survives restarts
+fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// Every output saves its state separately
+fn get_state_file(output: Option<&str>) -> PathBuf {
+    let mut state_dir = state_dir();
+    match output {
+        Some(output) => state_dir.push(format!("state-{output}.json")),
+        None => state_dir.push("state.json"),
+    }
+    state_dir
+}
+
 fn get_socket(args: &DaemonArgs) -> UnixSocketWithDrop {
     let path = args.socket.as_ref().map_or_else(
         || {
@@ -267,119 +279,337 @@ fn get_socket(args: &DaemonArgs) -> UnixSocketWithDrop {
     UnixSocketWithDrop { path, socket }
 }
 
-fn read_from_stream(mut stream: &UnixStream) -> String {
-    let string = String::new();
+// Thread: Client <---> Server
+fn handle_connection(
+    stream: UnixStream,
+    outputs: &Arc<Outputs>,
+    reloader: &Arc<Mutex<ConfigReloader>>,
+) -> bool {
+    info!("Handle new connection");
+    match protocol::read_message::<Hello>(&stream) {
+        Ok(hello) if hello.version == PROTOCOL_VERSION => {
+            if let Err(err) = protocol::write_message(&stream, &Response::Ok) {
+                error!("Couldn't answer handshake: {err}");
+                return false;
+            }
+        }
+        Ok(hello) => {
+            let response = Response::Error {
+                code: ErrorCode::VersionMismatch,
+                message: format!(
+                    "Client uses protocol version {}, daemon uses version {}",
+                    hello.version, PROTOCOL_VERSION
+                ),
+            };
+            if let Err(err) = protocol::write_message(&stream, &response) {
+                error!("Couldn't answer handshake: {err}");
+            }
+            return false;
+        }
+        Err(err) => {
+            error!("Invalid handshake: {err}");
+            return false;
+        }
+    }
+
+    let (response, stop_server) = match protocol::read_message::<Envelope>(&stream) {
+        Ok(Envelope {
+            output,
+            request: Request::Watch,
+        }) => match outputs.lock(output.as_deref()) {
+            Ok(mut states) => {
+                let (sender, events) = mpsc::channel();
+                for state in &mut states {
+                    state.subscribe(sender.clone());
+                }
+                thread::spawn(move || send_events(stream, events));
+                return false;
+            }
+            Err(err) => (err.into(), false),
+        },
+        Ok(Envelope { output, request }) => {
+            debug!("Got {:?} for {:?}", request, output);
+            let stop_server = matches!(request, Request::Stop);
+            let response = handle_request(request, output.as_deref(), outputs, reloader);
+            (response, stop_server)
+        }
+        Err(err) => (
+            Response::Error {
+                code: ErrorCode::BadRequest,
+                message: err.to_string(),
+            },
+            false,
+        ),
+    };
 
-    //First run get length
-    let mut buf: [u8; 8] = [0; 8];
+    if let Err(err) = protocol::write_message(&stream, &response) {
+        error!("Couldn't send response: {err}");
+    }
+    stop_server
+}
 
-    if stream.read_exact(&mut buf).is_err() {
-        return string;
+fn handle_request(
+    request: Request,
+    output: Option<&str>,
+    outputs: &Arc<Outputs>,
+    reloader: &Arc<Mutex<ConfigReloader>>,
+) -> Response {
+    if let Request::Monitors = request {
+        return match monitors::discover(reloader, outputs) {
+            Ok(monitors) => Response::Value {
+                value: monitors
+                    .iter()
+                    .map(|monitor| monitor.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            },
+            Err(err) => err.into(),
+        };
     }
 
-    let mut buffer = vec![0; usize::from_ne_bytes(buf)];
-    if stream.read_exact(&mut buffer).is_err() {
-        string
-    } else {
-        String::from_utf8(buffer).unwrap()
+    let query = matches!(
+        request,
+        Request::Get { .. }
+            | Request::Status
+            | Request::SavePlaylist { .. }
+            | Request::List { .. }
+            | Request::Stats { .. }
+    );
+    // The config applies to all outputs
+    let reload = matches!(request, Request::Reload);
+    let output = output.filter(|_| !reload);
+
+    // The selector command of external mode runs before locking everything
+    let selected = match request {
+        Request::Next => match outputs.lock(output) {
+            Ok(mut states) => {
+                let queries: Vec<_> = states
+                    .iter_mut()
+                    .map(|state| state.selector_query())
+                    .collect();
+                drop(states);
+                queries
+                    .into_iter()
+                    .map(|query| query.map(|query| query.and_then(SelectorQuery::run)))
+                    .collect()
+            }
+            Err(err) => return err.into(),
+        },
+        _ => Vec::new(),
+    };
+
+    let (response, pending) = {
+        // Always lock the reloader before the states
+        let mut reloader = reloader.lock().unwrap();
+        let mut states = match outputs.lock(output) {
+            Ok(states) => states,
+            Err(err) => return err.into(),
+        };
+        for state in &mut states {
+            // Left over from an update this request didn't cause
+            state.take_pending();
+        }
+        let response = handle_locked_request(request, &mut states, &mut reloader, selected);
+        let pending: Vec<_> = states
+            .iter_mut()
+            .filter_map(|state| state.take_pending())
+            .collect();
+        if !query {
+            states.iter().for_each(|state| state.store());
+        }
+        (response, pending)
+    };
+    if reload && matches!(response, Response::Ok) {
+        reload::start_threads(reloader, outputs);
     }
-}
 
-#[derive(Parser)]
-struct ClientMessage {
-    #[clap(subcommand)]
-    command: crate::command::Command,
+    // Wait for the wallpaper commands without blocking everyone else
+    match response {
+        Response::Ok => pending
+            .into_iter()
+            .map(|pending| pending.wait())
+            .fold(Ok(()), Result::and)
+            .into(),
+        response => response,
+    }
 }
 
-// Thread: Client <---> Server
-fn handle_connection(mut stream: UnixStream, state: Arc<Mutex<State>>) -> bool {
-    use crate::command::*;
-    use std::io::prelude::*;
+fn handle_locked_request(
+    request: Request,
+    states: &mut [MutexGuard<'_, State>],
+    reloader: &mut ConfigReloader,
+    selected: Vec<Option<Result<PathBuf, Error>>>,
+) -> Response {
+    use crate::command::GetArgs;
+
+    // Every output is changed even if one of them fails, the first error is reported
+    let mut for_each = |f: &mut dyn FnMut(&mut State) -> Result<(), Error>| -> Response {
+        states
+            .iter_mut()
+            .map(|state| f(state))
+            .fold(Ok(()), Result::and)
+            .into()
+    };
 
-    info!("Handle new connection");
-    let line = read_from_stream(&stream);
-    let mut response = "".to_string();
-
-    debug!("Got {}", &line);
-    let mut split: Vec<&str> = line.split(' ').collect();
-    split.insert(0, " ");
-    let mut stop_server = false;
-    match ClientMessage::parse_from(split).command {
-        Command::Next => state
-            .lock()
-            .unwrap()
-            .change_image(ChangeImageDirection::Next),
-        Command::Stop => stop_server = true,
-        Command::Previous => state
-            .lock()
-            .unwrap()
-            .change_image(ChangeImageDirection::Previous),
-        Command::Mode(mode) => match mode {
-            ModeArgs::Linear => state.lock().unwrap().update_action(NextImage::Linear, None),
-            ModeArgs::Random => state.lock().unwrap().update_action(NextImage::Random, None),
-            ModeArgs::Static(img) => match img.path {
-                Some(path) => state
-                    .lock()
-                    .unwrap()
-                    .update_action(NextImage::Static, Some(path)),
-                None => state.lock().unwrap().update_action(NextImage::Static, None),
-            },
+    match request {
+        Request::Next => {
+            // Answers of the selector commands, in the order of the states
+            let mut selected = selected.into_iter();
+            for_each(&mut |state| state.skip(selected.next().flatten()))
+        }
+        Request::Stop => Response::Ok,
+        Request::Previous => {
+            for_each(&mut |state| state.change_image(ChangeImageDirection::Previous))
+        }
+        Request::Mode { mode, image } => {
+            for_each(&mut |state| state.update_action(mode, image.clone()))
+        }
+        Request::Fallback => for_each(&mut |state| state.save()),
+        Request::Interval { seconds } => for_each(&mut |state| {
+            state.change_interval(Duration::from_secs(seconds));
+            Ok(())
+        }),
+        Request::Get { what } => {
+            let get = |state: &State| match what {
+                GetArgs::Wallpaper => state.get_current_image().to_string_lossy().into_owned(),
+                GetArgs::Duration => state.get_change_interval().as_secs().to_string(),
+                GetArgs::Mode => match state.get_action() {
+                    NextImage::Linear => "Linear".to_string(),
+                    NextImage::Static => "Static".to_string(),
+                    NextImage::Random => "Random".to_string(),
+                    NextImage::Shuffle => "Shuffle".to_string(),
+                    NextImage::External => "External".to_string(),
+                    NextImage::Weighted => "Weighted".to_string(),
+                    NextImage::Favorites => "Favorites".to_string(),
+                    NextImage::LeastRecent => "LeastRecent".to_string(),
+                },
+                GetArgs::Fallback => state.get_fallback().to_string(),
+                GetArgs::WpDir => state.describe_image_dirs().join("\n"),
+                GetArgs::Sources => state.describe_sources().join("\n"),
+                GetArgs::Rating => state
+                    .rating()
+                    .map_or_else(|| "unrated".to_owned(), |stars| stars.to_string()),
+            };
+            let value = match states {
+                [state] => get(state),
+                // Every line is prefixed with its output
+                states => states
+                    .iter()
+                    .flat_map(|state| {
+                        let output = state.get_output().unwrap_or_default();
+                        get(state)
+                            .lines()
+                            .map(|line| format!("{output}: {line}"))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            Response::Value { value }
+        }
+        Request::WpDir { path } => for_each(&mut |state| state.set_image_dir(path.clone())),
+        Request::Source { name, enabled } => {
+            for_each(&mut |state| state.set_source_enabled(&name, enabled))
+        }
+        Request::Rate { stars } => match states {
+            [state] => state.rate(stars).into(),
+            _ => Error::OutputRequired.into(),
+        },
+        Request::Mark {
+            list,
+            image,
+            marked,
+        } => match states {
+            [state] => state.mark(list, image, marked).into(),
+            // The lists are shared, so any state will do for a given image
+            [state, ..] if image.is_some() => state.mark(list, image, marked).into(),
+            _ => Error::OutputRequired.into(),
         },
-        Command::Fallback => state.lock().unwrap().save(),
-        Command::Interval(d) => {
-            state.lock().unwrap().change_interval(d.duration);
+        Request::List { list } => Response::Value {
+            // The lists are shared, so any state will do
+            value: states[0]
+                .list(list)
+                .iter()
+                .map(|image| image.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        },
+        Request::SavePlaylist { path, history } => {
+            let images = playlists(states, history);
+            info!(
+                "Saving {} images to {}",
+                images.len(),
+                path.to_string_lossy()
+            );
+            playlist::write(&path, &images).map_err(Error::from).into()
         }
-        Command::Get(what) => {
-            response = match what {
-                GetArgs::Wallpaper => state
-                    .lock()
-                    .unwrap()
-                    .get_current_image()
-                    .clone()
-                    .to_str()
-                    .unwrap_or("ERROR")
-                    .to_owned(),
-                GetArgs::Duration => state
-                    .lock()
-                    .unwrap()
-                    .get_change_interval()
-                    .as_secs()
-                    .to_string(),
-                GetArgs::Mode => {
-                    let action = state.lock().unwrap().get_action();
-                    match action {
-                        NextImage::Linear => "Linear".to_string(),
-                        NextImage::Static => "Static".to_string(),
-                        NextImage::Random => "Random".to_string(),
-                    }
-                }
-                GetArgs::Fallback => state.lock().unwrap().get_fallback().to_string(),
-                GetArgs::WpDir => state.lock().unwrap().get_image_dir().to_str().unwrap().to_owned(),
+        Request::Stats { top } => {
+            // The statistics are shared, so any state will do
+            match states[0].report(&playlists(states, false), top) {
+                Ok(stats) => Response::Stats { stats },
+                Err(err) => err.into(),
             }
         }
-        Command::Daemon(_) => todo!(),
-        Command::WpDir(wallpaper_directory) => {
-            state.lock().unwrap().set_image_dir(wallpaper_directory.path);
-        }
+        Request::Status => Response::Status {
+            outputs: states.iter().map(|state| state.status()).collect(),
+        },
+        Request::Reload => reloader.reload(states).into(),
+        Request::Watch => unreachable!("Subscriptions are handled by the connection"),
+        Request::Monitors => unreachable!("Discovery doesn't hold the locks"),
     }
+}
 
-    stream.write_all(response.as_bytes()).unwrap();
-    stop_server
+/// The playlists of the states joined, every image once in the order of the outputs
+fn playlists(states: &[MutexGuard<'_, State>], history: bool) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    states
+        .iter()
+        .flat_map(|state| state.playlist(history))
+        .filter(|image| seen.insert(image.clone()))
+        .collect()
+}
+
+// Thread: Server ---> Subscriber
+fn send_events(mut stream: UnixStream, events: Receiver<Event>) {
+    if let Err(err) = protocol::write_message(&stream, &Response::Ok) {
+        error!("Couldn't answer subscription: {err}");
+        return;
+    }
+    for event in events {
+        let line = serde_json::to_string(&event).expect("Events are always serializable");
+        if writeln!(stream, "{line}").is_err() {
+            info!("Subscriber disconnected");
+            return;
+        }
+    }
 }
 
 fn change_interval(data: Arc<Mutex<State>>) {
     let mut time = {
         //Go out of scope to unlock again
-        let unlocked = data.lock().unwrap();
-        unlocked.get_change_interval()
+        let mut unlocked = data.lock().unwrap();
+        unlocked.schedule_next_change()
     };
     loop {
         sleep(time);
         {
             //Go out of scope to unlock again
+            // The selector command of external mode runs unlocked
+            let query = data.lock().unwrap().selector_query();
+            let selected = query.map(|query| query.and_then(SelectorQuery::run));
             let mut unlocked = data.lock().unwrap();
-            unlocked.change_image(ChangeImageDirection::Next);
-            time = unlocked.get_change_interval()
+            let result = match selected {
+                Some(selected) => unlocked.show_selected(selected),
+                None => unlocked.change_image(ChangeImageDirection::Next),
+            };
+            match result {
+                Ok(()) | Err(Error::Fallback | Error::StaticMode) => {}
+                Err(err) => error!("Couldn't change the wallpaper: {err}"),
+            }
+            // Errors are logged by the worker
+            unlocked.take_pending();
+            unlocked.store();
+            time = unlocked.schedule_next_change()
         };
     }
 }
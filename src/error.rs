@@ -0,0 +1,111 @@
+use std::{fmt::Display, io, path::PathBuf};
+
+use crate::library::List;
+use crate::protocol::ErrorCode;
+
+/// Errors the daemon reports back to the client
+#[derive(Debug)]
+pub enum Error {
+    /// The wallpaper directory doesn't contain any images
+    NoImages(PathBuf),
+    /// The path isn't a directory or playlist that can be read
+    InvalidDirectory(PathBuf),
+    /// The path isn't an image file
+    InvalidImage(PathBuf),
+    /// There is no image to go back to
+    NoPrevious,
+    /// The image can't change while the fallback is shown
+    Fallback,
+    /// The image can't change while in static mode
+    StaticMode,
+    /// The wallpaper command failed
+    Command(String),
+    /// The config file is invalid
+    Config(String),
+    /// No output with this name is configured
+    UnknownOutput(String),
+    /// No source with this name is configured
+    UnknownSource(String),
+    /// The request is about the image of one output, but several are selected
+    OutputRequired,
+    /// The image can't be removed from a list it isn't in
+    NotInList(PathBuf, List),
+    /// The image was banned and is never shown
+    Banned(PathBuf),
+    /// Favorites mode has nothing to show
+    NoFavorites,
+    /// Ratings go from one to five stars
+    InvalidRating(u8),
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::NoImages(_) | Error::NoFavorites => ErrorCode::NoImages,
+            Error::InvalidDirectory(_)
+            | Error::InvalidImage(_)
+            | Error::NotInList(..)
+            | Error::Banned(_) => ErrorCode::InvalidPath,
+            Error::NoPrevious | Error::Fallback | Error::StaticMode => ErrorCode::InvalidState,
+            Error::Command(_) => ErrorCode::CommandFailed,
+            Error::Config(_) => ErrorCode::InvalidConfig,
+            Error::UnknownOutput(_) | Error::OutputRequired => ErrorCode::UnknownOutput,
+            Error::UnknownSource(_) => ErrorCode::UnknownSource,
+            Error::InvalidRating(_) => ErrorCode::BadRequest,
+            Error::Io(_) => ErrorCode::Io,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NoImages(dir) => write!(f, "No images found in {}", dir.to_string_lossy()),
+            Error::InvalidDirectory(dir) => {
+                write!(
+                    f,
+                    "{} isn't a readable directory or playlist",
+                    dir.to_string_lossy()
+                )
+            }
+            Error::InvalidImage(path) => {
+                write!(f, "{} isn't an image file", path.to_string_lossy())
+            }
+            Error::NoFavorites => write!(f, "There are no favorites, add some with 'wp favorite'"),
+            Error::NoPrevious => write!(f, "There is no previous image"),
+            Error::Fallback => write!(f, "Can't change image while using fallback"),
+            Error::StaticMode => write!(f, "Can't change image while in static mode"),
+            Error::Command(msg) => write!(f, "Wallpaper command failed: {msg}"),
+            Error::Config(msg) => write!(f, "Invalid config file {msg}"),
+            Error::UnknownOutput(name) => write!(f, "There is no output named {name}"),
+            Error::UnknownSource(name) => write!(f, "There is no source named {name}"),
+            Error::NotInList(image, list) => {
+                let list = match list {
+                    List::Favorites => "the favorites",
+                    List::Banned => "the banned images",
+                };
+                write!(f, "{} isn't in {list}", image.to_string_lossy())
+            }
+            Error::Banned(image) => write!(f, "{} is banned", image.to_string_lossy()),
+            Error::OutputRequired => {
+                write!(
+                    f,
+                    "Every output shows its own image, choose one with --output"
+                )
+            }
+            Error::InvalidRating(stars) => {
+                write!(f, "Can't rate with {stars} stars, ratings go from 1 to 5")
+            }
+            Error::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,564 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsStr,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use log::{debug, error, info, warn};
+use rand::Rng;
+
+use crate::error::Error;
+use crate::library;
+use crate::order::{self, Order, SortKey};
+use crate::playlist;
+use crate::scan::{self, ScanConfig};
+use crate::sets::SetsConfig;
+use crate::state::State;
+
+/// What the index considers an image
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexOptions {
+    pub scan: ScanConfig,
+    pub sets: Option<SetsConfig>,
+    /// Playlists keep their own order
+    pub order: Order,
+    pub descending: bool,
+}
+
+/// In-memory list of the candidate images in the wallpaper directory
+///
+/// Built once and kept up to date through inotify, so choosing an image never touches the disk.
+/// With image sets every entry is a set, see [`ImageIndex::members`].
+/// `dir` may also be a playlist file, see [`crate::playlist`].
+#[derive(Debug, Default)]
+pub struct ImageIndex {
+    dir: PathBuf,
+    /// Whether `dir` is a playlist, whose directory is watched instead
+    playlist: bool,
+    images: Vec<PathBuf>,
+    options: IndexOptions,
+    /// Images of every set by role
+    members: HashMap<PathBuf, BTreeMap<String, PathBuf>>,
+    /// Position of every image in `images`
+    positions: HashMap<PathBuf, usize>,
+    /// Width divided by height, read from the file header when the image is added
+    aspect_ratios: HashMap<PathBuf, Option<f64>>,
+    /// Every directory which was read, with its device and inode to notice loops
+    dirs: HashMap<PathBuf, (u64, u64)>,
+    visited: HashSet<(u64, u64)>,
+    watches: Option<Watches>,
+    /// Directory of every watch
+    watched: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl ImageIndex {
+    /// Read all images in `dir` and its sub-directories, or in the playlist `dir`
+    pub fn build(dir: &Path, options: IndexOptions) -> Result<Self, Error> {
+        if !dir.is_dir() && !dir.is_file() {
+            return Err(Error::InvalidDirectory(dir.to_path_buf()));
+        }
+        let mut index = ImageIndex::empty(dir, options);
+        index.scan();
+        info!(
+            "Indexed {} {} in {}",
+            index.images.len(),
+            index.kind(),
+            dir.to_string_lossy()
+        );
+        Ok(index)
+    }
+
+    /// Index for a directory which can't be read
+    pub fn empty(dir: &Path, options: IndexOptions) -> Self {
+        ImageIndex {
+            dir: dir.to_path_buf(),
+            playlist: dir.is_file(),
+            options,
+            ..Default::default()
+        }
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    /// What the entries are, "images" or "sets"
+    pub fn kind(&self) -> &'static str {
+        if self.options.sets.is_some() {
+            "sets"
+        } else {
+            "images"
+        }
+    }
+
+    pub fn images(&self) -> &[PathBuf] {
+        &self.images
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.positions.contains_key(path)
+    }
+
+    /// Where `path` is in [`ImageIndex::images`]
+    pub fn position(&self, path: &Path) -> Option<usize> {
+        self.positions.get(path).copied()
+    }
+
+    pub fn random(&self) -> Option<&PathBuf> {
+        if self.images.is_empty() {
+            None
+        } else {
+            Some(&self.images[rand::thread_rng().gen_range(0..self.images.len())])
+        }
+    }
+
+    pub fn options(&self) -> &IndexOptions {
+        &self.options
+    }
+
+    fn sets(&self) -> Option<&SetsConfig> {
+        self.options.sets.as_ref()
+    }
+
+    /// The images of a set by role, `None` if `path` isn't a set
+    pub fn members(&self, path: &Path) -> Option<&BTreeMap<String, PathBuf>> {
+        self.members.get(path)
+    }
+
+    /// Width divided by height of an image, `None` if the file header can't be read or it's a set
+    pub fn aspect_ratio(&self, path: &Path) -> Option<f64> {
+        self.aspect_ratios.get(path).copied().flatten()
+    }
+
+    fn scan(&mut self) {
+        if self.playlist {
+            self.read_playlist();
+        } else {
+            self.walk(&self.dir.clone());
+            self.sort();
+        }
+    }
+
+    /// Sort key of an entry, sets are sorted by their first image
+    fn sort_key(&self, entry: &Path) -> SortKey {
+        let file = self
+            .members
+            .get(entry)
+            .and_then(|members| members.values().next())
+            .map_or(entry, PathBuf::as_path);
+        self.options.order.key(entry, file)
+    }
+
+    fn sort(&mut self) {
+        let mut images = std::mem::take(&mut self.images);
+        images.sort_by_cached_key(|image| self.sort_key(image));
+        if self.options.descending {
+            images.reverse();
+        }
+        self.images = images;
+        self.positions.clear();
+        self.reindex(0);
+    }
+
+    /// Move an entry which was added at the end to its place in the order
+    fn place(&mut self, entry: PathBuf) {
+        let key = self.sort_key(&entry);
+        let idx = self.images.partition_point(|image| {
+            order::direction(self.sort_key(image).cmp(&key), self.options.descending)
+                == Ordering::Less
+        });
+        self.images.insert(idx, entry);
+        self.reindex(idx);
+    }
+
+    /// Add the images of the playlist in its order
+    fn read_playlist(&mut self) {
+        // Editors replace the file, so the directory is watched for it
+        if let Some(parent) = self.dir.parent().map(Path::to_path_buf) {
+            if let Ok(metadata) = fs::metadata(&parent) {
+                self.dirs
+                    .insert(parent.clone(), (metadata.dev(), metadata.ino()));
+                self.watch_dir(&parent);
+            }
+        }
+        let entries = match playlist::read(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Couldn't read {}: {err}", self.dir.to_string_lossy());
+                return;
+            }
+        };
+        for entry in entries {
+            if !entry.is_file() {
+                warn!(
+                    "{} lists {}, which isn't a file",
+                    self.dir.to_string_lossy(),
+                    entry.to_string_lossy()
+                );
+                continue;
+            }
+            self.add_file(entry);
+        }
+    }
+
+    /// Read a directory once, returns whether an entry was added
+    fn walk(&mut self, dir: &Path) -> bool {
+        let Ok(metadata) = fs::metadata(dir) else {
+            return false;
+        };
+        let id = (metadata.dev(), metadata.ino());
+        if !self.visited.insert(id) {
+            debug!("Skipping {}, it was already read", dir.to_string_lossy());
+            return false;
+        }
+        self.dirs.insert(dir.to_path_buf(), id);
+        self.watch_dir(dir);
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Couldn't read {}: {err}", dir.to_string_lossy());
+                return false;
+            }
+        };
+        let mut added = false;
+        for entry in entries.filter_map(|res| res.ok().map(|e| e.path())) {
+            added |= self.add(entry);
+        }
+        added
+    }
+
+    /// Add a file or directory of the wallpaper directory, returns whether an entry was added
+    fn add(&mut self, path: PathBuf) -> bool {
+        let relative = scan::relative(&self.dir, &path);
+        let scan = &self.options.scan;
+        if scan.skips(&relative) {
+            return false;
+        }
+        if path.is_dir() {
+            let depth = relative.components().count();
+            if depth == 1 && self.sets().is_some_and(|sets| sets.folders) {
+                return self.add_folder_set(path);
+            }
+            if depth > scan.max_depth {
+                return false;
+            }
+            let is_link = fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink());
+            if is_link && !scan.follow_symlinks {
+                debug!("Not following the symlink {}", path.to_string_lossy());
+                return false;
+            }
+            return self.walk(&path);
+        }
+        if !path.is_file() || !scan.accepts(&path, &relative) {
+            return false;
+        }
+        self.add_file(path)
+    }
+
+    /// Add an image, or the set it belongs to
+    fn add_file(&mut self, path: PathBuf) -> bool {
+        let Some(sets) = &self.options.sets else {
+            return self.push(path);
+        };
+        let Some((set, role)) = sets.classify(&path) else {
+            return false;
+        };
+        self.members
+            .entry(set.clone())
+            .or_default()
+            .insert(role, path);
+        self.push(set)
+    }
+
+    /// Add a directory whose images form a set
+    fn add_folder_set(&mut self, path: PathBuf) -> bool {
+        let Ok(entries) = fs::read_dir(&path) else {
+            return false;
+        };
+        let members: BTreeMap<String, PathBuf> = entries
+            .filter_map(|res| res.ok().map(|e| e.path()))
+            .filter(|member| {
+                member.is_file()
+                    && self
+                        .options
+                        .scan
+                        .accepts(member, &scan::relative(&self.dir, member))
+            })
+            .filter_map(|member| {
+                let role = member.file_stem()?.to_str()?.to_owned();
+                Some((role, member))
+            })
+            .collect();
+        if members.is_empty() {
+            return false;
+        }
+        self.members
+            .entry(path.clone())
+            .or_default()
+            .extend(members);
+        self.push(path)
+    }
+
+    /// Add an entry unless it already exists
+    fn push(&mut self, entry: PathBuf) -> bool {
+        if self.positions.contains_key(&entry) {
+            return false;
+        }
+        if !self.members.contains_key(&entry) {
+            self.aspect_ratios
+                .insert(entry.clone(), read_aspect_ratio(&entry));
+        }
+        self.positions.insert(entry.clone(), self.images.len());
+        self.images.push(entry);
+        true
+    }
+
+    /// Returns the image files which were added
+    fn insert(&mut self, path: PathBuf) -> Vec<PathBuf> {
+        let name = path.to_string_lossy().into_owned();
+        let start = self.images.len();
+        if !self.add(path) {
+            return Vec::new();
+        }
+        debug!("Adding {name} to the index");
+        let mut files = Vec::new();
+        for entry in self.images.split_off(start) {
+            match self.members.get(&entry) {
+                Some(members) => files.extend(members.values().cloned()),
+                None => files.push(entry.clone()),
+            }
+            self.place(entry);
+        }
+        files
+    }
+
+    fn remove(&mut self, path: &Path) {
+        let mut entry = path.to_path_buf();
+        // A member of a set is gone, the set stays as long as it has other members
+        if let Some((set, role)) = self
+            .sets()
+            .filter(|_| !self.members.contains_key(path))
+            .and_then(|sets| sets.classify(path))
+        {
+            let Some(members) = self.members.get_mut(&set) else {
+                return;
+            };
+            members.remove(&role);
+            if !members.is_empty() {
+                return;
+            }
+            entry = set;
+        }
+        self.members.remove(&entry);
+
+        if let Some(idx) = self.positions.remove(&entry) {
+            debug!("Removing {} from the index", entry.to_string_lossy());
+            self.images.remove(idx);
+            self.aspect_ratios.remove(&entry);
+            self.reindex(idx);
+        }
+    }
+
+    /// Remove a directory which was read, with everything in it
+    fn remove_tree(&mut self, dir: &Path) {
+        debug!("Removing {} from the index", dir.to_string_lossy());
+        let inside = |path: &PathBuf| path.starts_with(dir);
+        let visited = &mut self.visited;
+        self.dirs.retain(|path, id| {
+            if inside(path) {
+                visited.remove(id);
+            }
+            !inside(path)
+        });
+        let gone: Vec<WatchDescriptor> = self
+            .watched
+            .iter()
+            .filter(|(_, path)| inside(path))
+            .map(|(wd, _)| wd.clone())
+            .collect();
+        for wd in gone {
+            self.watched.remove(&wd);
+            if let Some(watches) = &mut self.watches {
+                // Fails if the directory was already deleted
+                let _ = watches.remove(wd);
+            }
+        }
+        self.images.retain(|image| !inside(image));
+        self.members.retain(|set, _| !inside(set));
+        self.aspect_ratios.retain(|image, _| !inside(image));
+        self.positions.clear();
+        self.reindex(0);
+    }
+
+    /// Update the positions of all images starting at `start`
+    fn reindex(&mut self, start: usize) {
+        for (idx, image) in self.images.iter().enumerate().skip(start) {
+            self.positions.insert(image.clone(), idx);
+        }
+    }
+
+    /// Keep the index up to date through the given inotify instance
+    pub fn watch(&mut self, watches: Watches) {
+        self.watches = Some(watches);
+        let dirs: Vec<PathBuf> = self.dirs.keys().cloned().collect();
+        for dir in dirs {
+            self.watch_dir(&dir);
+        }
+    }
+
+    fn watch_dir(&mut self, dir: &Path) {
+        let Some(watches) = &mut self.watches else {
+            return;
+        };
+        let mask = WatchMask::CREATE
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO
+            | WatchMask::DELETE_SELF
+            | WatchMask::MOVE_SELF;
+        match watches.add(dir, mask) {
+            Ok(wd) => {
+                self.watched.insert(wd, dir.to_path_buf());
+            }
+            Err(err) => error!("Couldn't watch {}: {err}", dir.to_string_lossy()),
+        }
+    }
+
+    /// The watches, so a new index can take them over
+    pub fn take_watches(&mut self) -> Option<Watches> {
+        let mut watches = self.watches.take()?;
+        for (wd, _) in self.watched.drain() {
+            // Fails if the directory was already deleted
+            let _ = watches.remove(wd);
+        }
+        Some(watches)
+    }
+
+    /// Apply a change in the wallpaper directory
+    /// Returns the image files which were added
+    fn handle_event(
+        &mut self,
+        wd: &WatchDescriptor,
+        mask: EventMask,
+        name: Option<&OsStr>,
+    ) -> Vec<PathBuf> {
+        let Some(dir) = self.watched.get(wd) else {
+            // Event for a directory which isn't used anymore
+            return Vec::new();
+        };
+        if mask.intersects(EventMask::DELETE_SELF | EventMask::MOVE_SELF) {
+            // Sub-directories are removed through the event of their parent
+            if *dir == self.dir {
+                warn!("Wallpaper directory {} is gone", self.dir.to_string_lossy());
+                self.clear();
+            }
+            return Vec::new();
+        }
+        let Some(name) = name else {
+            return Vec::new();
+        };
+        if self.playlist {
+            if self.dir.file_name() == Some(name) {
+                info!("Playlist {} changed", self.dir.to_string_lossy());
+                self.rebuild();
+            }
+            return Vec::new();
+        }
+        let path = dir.join(name);
+        // Files are empty when created, so they are only sniffed once they were written
+        if mask.intersects(EventMask::CREATE | EventMask::CLOSE_WRITE | EventMask::MOVED_TO) {
+            return self.insert(path);
+        }
+        if mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+            if self.dirs.contains_key(&path) {
+                self.remove_tree(&path);
+            } else {
+                self.remove(&path);
+            }
+        }
+        Vec::new()
+    }
+
+    fn clear(&mut self) {
+        self.images.clear();
+        self.positions.clear();
+        self.members.clear();
+        self.aspect_ratios.clear();
+        self.dirs.clear();
+        self.visited.clear();
+    }
+
+    /// Read the directory or playlist again, used when inotify lost events or the playlist changed
+    fn rebuild(&mut self) {
+        let watches = self.take_watches();
+        self.clear();
+        self.watches = watches;
+        self.scan();
+        info!(
+            "Indexed {} {} in {}",
+            self.images.len(),
+            self.kind(),
+            self.dir.to_string_lossy()
+        );
+    }
+}
+
+/// Width divided by height, `None` if the file header can't be read
+fn read_aspect_ratio(path: &Path) -> Option<f64> {
+    match imagesize::size(path) {
+        Ok(size) if size.height > 0 => Some(size.width as f64 / size.height as f64),
+        Ok(_) => None,
+        Err(err) => {
+            debug!(
+                "Couldn't read the size of {}: {err}",
+                path.to_string_lossy()
+            );
+            None
+        }
+    }
+}
+
+// Thread: Keep the image index up to date
+pub fn watch_images(mut inotify: Inotify, state: Arc<Mutex<State>>) {
+    let mut buffer = [0; 4096];
+    loop {
+        let events = match inotify.read_events_blocking(&mut buffer) {
+            Ok(events) => events,
+            Err(err) => {
+                error!("Couldn't read inotify events: {err}");
+                return;
+            }
+        };
+
+        let mut state = state.lock().unwrap();
+        let mut added = Vec::new();
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                warn!("Missed changes in the wallpaper directories, reading them again");
+                state
+                    .sources_mut()
+                    .indexes_mut()
+                    .for_each(ImageIndex::rebuild);
+            } else {
+                for index in state.sources_mut().indexes_mut() {
+                    added.extend(index.handle_event(&event.wd, event.mask, event.name));
+                }
+            }
+        }
+        // Renamed images keep their ratings, reading them doesn't block the state
+        let library = state.library();
+        drop(state);
+        library::find_rated(&library, added);
+    }
+}
@@ -0,0 +1,147 @@
+//! What the user said about images, shared by all outputs and saved in the state directory
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::playlist;
+use crate::ratings::{self, Ratings};
+use crate::stats::DisplayStats;
+
+/// The lists `wp favorite` and `wp ban` add to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum List {
+    Favorites,
+    Banned,
+}
+
+#[derive(Debug, Default)]
+pub struct Library {
+    pub ratings: Ratings,
+    /// Images favorites mode rotates through
+    pub favorites: ImageList,
+    /// Images which are never chosen
+    pub banned: ImageList,
+    /// When and how long images were shown
+    pub stats: DisplayStats,
+}
+
+impl Library {
+    /// Read everything saved in `dir`
+    pub fn load(dir: &Path) -> Self {
+        Library {
+            ratings: Ratings::load(dir.join("ratings.json")),
+            favorites: ImageList::load(dir.join("favorites.m3u")),
+            banned: ImageList::load(dir.join("banned.m3u")),
+            stats: DisplayStats::load(dir.join("stats.json"), dir.join("events.jsonl")),
+        }
+    }
+
+    pub fn list(&self, list: List) -> &ImageList {
+        match list {
+            List::Favorites => &self.favorites,
+            List::Banned => &self.banned,
+        }
+    }
+
+    pub fn list_mut(&mut self, list: List) -> &mut ImageList {
+        match list {
+            List::Favorites => &mut self.favorites,
+            List::Banned => &mut self.banned,
+        }
+    }
+}
+
+/// Find out where rated images are now, so weighted mode knows their ratings after renames
+///
+/// Only images of a rated size are read, the library is unlocked while hashing them.
+pub fn find_rated(library: &Mutex<Library>, images: impl IntoIterator<Item = PathBuf>) {
+    if library.lock().unwrap().ratings.is_empty() {
+        return;
+    }
+    for image in images {
+        let Ok(size) = fs::metadata(&image).map(|metadata| metadata.len()) else {
+            continue;
+        };
+        if !library.lock().unwrap().ratings.may_be_rated(&image, size) {
+            continue;
+        }
+        match ratings::hash_file(&image) {
+            Ok(hash) => library.lock().unwrap().ratings.found(&image, hash),
+            Err(err) => debug!("Couldn't read {}: {err}", image.to_string_lossy()),
+        }
+    }
+}
+
+/// Images in the order they were added, saved as a playlist
+#[derive(Debug, Default)]
+pub struct ImageList {
+    file: Option<PathBuf>,
+    images: Vec<PathBuf>,
+    members: HashSet<PathBuf>,
+}
+
+impl ImageList {
+    /// Read the list saved in `file`, empty if there is none
+    fn load(file: PathBuf) -> Self {
+        let images = match playlist::read(&file) {
+            Ok(images) => images,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                error!("Couldn't read {}: {err}", file.to_string_lossy());
+                Vec::new()
+            }
+        };
+        ImageList {
+            file: Some(file),
+            members: images.iter().cloned().collect(),
+            images,
+        }
+    }
+
+    pub fn images(&self) -> &[PathBuf] {
+        &self.images
+    }
+
+    pub fn contains(&self, image: &Path) -> bool {
+        self.members.contains(image)
+    }
+
+    /// Add the image unless it is in the list already
+    pub fn add(&mut self, image: &Path) {
+        if self.members.insert(image.to_path_buf()) {
+            self.images.push(image.to_path_buf());
+            self.store();
+        }
+    }
+
+    /// Returns whether the image was in the list
+    pub fn remove(&mut self, image: &Path) -> bool {
+        if !self.members.remove(image) {
+            return false;
+        }
+        self.images.retain(|other| other != image);
+        self.store();
+        true
+    }
+
+    fn store(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        info!(
+            "Saving {} images to {}",
+            self.images.len(),
+            file.to_string_lossy()
+        );
+        if let Err(err) = playlist::write(file, &self.images) {
+            error!("Couldn't save {}: {err}", file.to_string_lossy());
+        }
+    }
+}
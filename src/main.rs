@@ -1,15 +1,38 @@
 use clap::Parser;
-use command::Command;
-use std::io::prelude::*;
+use command::{Command, StatsArgs, StatusArgs};
+use protocol::{Envelope, Hello, Request, Response, Status, PROTOCOL_VERSION};
+use stats::{ImageCount, Report};
+use std::io::{self, prelude::*};
 use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::exit;
 use std::str::FromStr;
 
 use log::info;
 
+mod backend;
+mod bag;
 mod command;
+mod config;
 mod daemon;
+mod error;
+mod index;
+mod library;
+mod monitors;
+mod order;
+mod outputs;
+mod playlist;
+mod protocol;
+mod ratings;
+mod reload;
+mod scan;
+mod select;
+mod sets;
+mod sources;
+mod span;
 mod state;
+mod stats;
+mod worker;
 
 #[derive(Parser)]
 #[clap(version)]
@@ -17,6 +40,9 @@ pub struct Arguments {
     /// Socket for communication
     #[clap(short, long, value_parser, value_name = "FILE")]
     socket: Option<PathBuf>,
+    /// Only change or query this output, all outputs if not given
+    #[clap(short, long, value_name = "NAME")]
+    output: Option<String>,
     #[clap(subcommand)]
     command: command::Command,
 }
@@ -36,27 +62,160 @@ fn main() {
         }
     });
 
-    //TODO make not shit
-
-    if let Command::Daemon(args) = args.command {
-        daemon::start_daemon(args);
-    } else {
-        let args = args.command.to_string();
-        let len = args.len();
-        let mut socket = UnixStream::connect(socket).expect("Socket not found");
-
-        info!("Sending {:?}", args);
-        socket.write_all(&len.to_ne_bytes()).unwrap();
-        socket.write_all(args.trim().as_bytes()).unwrap();
-        info!("{:?}", &len.to_ne_bytes());
-        info!("{:?}", args.trim().as_bytes());
-        socket.flush().unwrap();
-
-        info!("Reading:");
-        let mut line = String::new();
-        socket
-            .read_to_string(&mut line)
-            .expect("Couldn't read string");
-        println!("{}", line);
+    let status_args = match &args.command {
+        Command::Status(status_args) => Some(status_args.clone()),
+        _ => None,
+    };
+    let stats_args = match &args.command {
+        Command::Stats(stats_args) => Some(stats_args.clone()),
+        _ => None,
+    };
+    let request = match args.command {
+        Command::Daemon(args) => return daemon::start_daemon(args),
+        command => command
+            .into_request()
+            .expect("Only the daemon runs locally"),
+    };
+
+    let watch = matches!(request, Request::Watch);
+    let envelope = Envelope {
+        output: args.output,
+        request,
+    };
+    match send_request(&socket, &envelope) {
+        Ok((stream, Response::Ok)) if watch => {
+            if let Err(err) = print_events(stream) {
+                eprintln!("Lost connection to the daemon: {}", err);
+                exit(1);
+            }
+        }
+        Ok((_, Response::Ok)) => {}
+        Ok((_, Response::Value { value })) => println!("{}", value),
+        Ok((_, Response::Status { outputs })) => {
+            print_status(&outputs, &status_args.unwrap_or_default())
+        }
+        Ok((_, Response::Stats { stats })) => print_stats(&stats, &stats_args.unwrap_or_default()),
+        Ok((_, Response::Error { code, message })) => {
+            eprintln!("Error ({}): {}", code, message);
+            exit(1);
+        }
+        Err(err) => {
+            eprintln!(
+                "Couldn't talk to the daemon at {}: {}",
+                socket.to_string_lossy(),
+                err
+            );
+            exit(1);
+        }
+    }
+}
+
+fn send_request(socket: &Path, request: &Envelope) -> io::Result<(UnixStream, Response)> {
+    let mut stream = UnixStream::connect(socket)?;
+
+    protocol::write_message(
+        &stream,
+        &Hello {
+            version: PROTOCOL_VERSION,
+        },
+    )?;
+    if let Response::Error { code, message } = protocol::read_message(&stream)? {
+        return Ok((stream, Response::Error { code, message }));
+    }
+
+    info!("Sending {:?}", request);
+    protocol::write_message(&stream, request)?;
+    stream.flush()?;
+
+    info!("Reading:");
+    let response = protocol::read_message(&stream)?;
+    Ok((stream, response))
+}
+
+fn print_status(outputs: &[Status], args: &StatusArgs) {
+    for (i, status) in outputs.iter().enumerate() {
+        if args.json {
+            println!(
+                "{}",
+                serde_json::to_string(status).expect("Status is always serializable")
+            );
+        } else if let Some(template) = &args.format {
+            match status.format(template) {
+                Ok(line) => println!("{}", line),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    exit(1);
+                }
+            }
+        } else {
+            if i > 0 {
+                println!();
+            }
+            for (name, value) in status.fields() {
+                // Only shown when the outputs rotate separately
+                if name == "output" && status.output.is_none() {
+                    continue;
+                }
+                println!("{}: {}", name, value);
+            }
+        }
+    }
+}
+
+fn print_stats(stats: &Report, args: &StatsArgs) {
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string(stats).expect("Statistics are always serializable")
+        );
+        return;
+    }
+    println!("Recorded showings: {}", stats.events);
+    let print_ranking = |title: &str, ranking: &[ImageCount]| {
+        println!("\n{title}:");
+        for entry in ranking {
+            println!("{:>6}  {}", entry.count, entry.image.to_string_lossy());
+        }
+    };
+    print_ranking("Most shown", &stats.most_shown);
+    print_ranking("Most skipped", &stats.most_skipped);
+
+    println!("\nNever shown: {} images", stats.never_shown_count);
+    for image in &stats.never_shown {
+        println!("        {}", image.to_string_lossy());
+    }
+    if stats.never_shown_count > stats.never_shown.len() {
+        println!(
+            "        and {} more",
+            stats.never_shown_count - stats.never_shown.len()
+        );
+    }
+
+    println!("\nTime shown per source:");
+    for source in &stats.sources {
+        let name = source
+            .source
+            .as_ref()
+            .map_or_else(|| "(no source)".into(), |dir| dir.to_string_lossy());
+        let (hours, minutes) = (source.seconds / 3600, source.seconds / 60 % 60);
+        println!("{hours:>3}h {minutes:02}m  {name}");
+    }
+
+    println!("\nImages by skip rate:");
+    let most = stats.skip_rates.iter().max().copied().unwrap_or(0).max(1);
+    for (i, count) in stats.skip_rates.iter().enumerate() {
+        let bar = "#".repeat((count * 40).div_ceil(most));
+        let line = format!("{:>3}-{:>3}%  {count:>6}  {bar}", i * 10, i * 10 + 10);
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Copy the event stream to stdout until the daemon closes the connection
+fn print_events(stream: UnixStream) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    for line in io::BufReader::new(stream).lines() {
+        writeln!(stdout, "{}", line?)?;
+        stdout.flush()?;
     }
+    Ok(())
 }
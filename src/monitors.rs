@@ -0,0 +1,230 @@
+//! Connected monitors as reported by the compositor
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    thread::sleep,
+};
+
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::backend::read_output;
+use crate::error::Error;
+use crate::outputs::Outputs;
+use crate::reload::ConfigReloader;
+
+/// Rotation and mirroring of an output, in the order of `wl_output::transform`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Normal,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270,
+}
+
+impl Transform {
+    const ALL: [Transform; 8] = [
+        Transform::Normal,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    /// Whether width and height are swapped on screen
+    pub fn is_rotated(&self) -> bool {
+        matches!(
+            self,
+            Transform::Rotate90
+                | Transform::Rotate270
+                | Transform::Flipped90
+                | Transform::Flipped270
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Transform::Normal => "normal",
+            Transform::Rotate90 => "90",
+            Transform::Rotate180 => "180",
+            Transform::Rotate270 => "270",
+            Transform::Flipped => "flipped",
+            Transform::Flipped90 => "flipped-90",
+            Transform::Flipped180 => "flipped-180",
+            Transform::Flipped270 => "flipped-270",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Monitor {
+    pub name: String,
+    /// Resolution of the current mode in pixels, before the transform
+    pub width: u32,
+    pub height: u32,
+    /// Position in the layout
+    pub x: i32,
+    pub y: i32,
+    pub scale: f64,
+    pub transform: Transform,
+}
+
+impl Monitor {
+    /// Width and height as seen on screen
+    pub fn size(&self) -> (u32, u32) {
+        if self.transform.is_rotated() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+impl Display for Monitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}x{}+{}+{} scale {} transform {}",
+            self.name,
+            self.width,
+            self.height,
+            self.x,
+            self.y,
+            self.scale,
+            self.transform.name()
+        )
+    }
+}
+
+/// Output of `hyprctl monitors -j`
+#[derive(Deserialize)]
+struct HyprlandMonitor {
+    name: String,
+    width: u32,
+    height: u32,
+    x: i32,
+    y: i32,
+    scale: f64,
+    transform: usize,
+    #[serde(default)]
+    disabled: bool,
+}
+
+/// Output of `wlr-randr --json`
+#[derive(Deserialize)]
+struct WlrOutput {
+    name: String,
+    enabled: bool,
+    modes: Vec<WlrMode>,
+    position: WlrPosition,
+    transform: String,
+    scale: f64,
+}
+
+#[derive(Deserialize)]
+struct WlrMode {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    current: bool,
+}
+
+#[derive(Deserialize)]
+struct WlrPosition {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Reported {
+    Hyprland(HyprlandMonitor),
+    Wlr(WlrOutput),
+}
+
+impl Reported {
+    /// `None` for disabled outputs
+    fn into_monitor(self) -> Option<Monitor> {
+        match self {
+            Reported::Hyprland(monitor) if !monitor.disabled => Some(Monitor {
+                name: monitor.name,
+                width: monitor.width,
+                height: monitor.height,
+                x: monitor.x,
+                y: monitor.y,
+                scale: monitor.scale,
+                transform: Transform::ALL
+                    .get(monitor.transform)
+                    .copied()
+                    .unwrap_or(Transform::Normal),
+            }),
+            Reported::Wlr(output) if output.enabled => {
+                let mode = output.modes.iter().find(|mode| mode.current)?;
+                Some(Monitor {
+                    width: mode.width,
+                    height: mode.height,
+                    name: output.name,
+                    x: output.position.x,
+                    y: output.position.y,
+                    scale: output.scale,
+                    transform: Transform::ALL
+                        .into_iter()
+                        .find(|transform| transform.name() == output.transform)
+                        .unwrap_or(Transform::Normal),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parse the JSON printed by `hyprctl monitors -j` or `wlr-randr --json`
+pub fn parse(json: &str) -> Result<Vec<Monitor>, Error> {
+    let reported: Vec<Reported> = serde_json::from_str(json)
+        .map_err(|err| Error::Command(format!("Couldn't parse the monitor list: {err}")))?;
+    Ok(reported
+        .into_iter()
+        .filter_map(Reported::into_monitor)
+        .collect())
+}
+
+/// Run the monitor command and hand every state the monitors it draws on
+pub fn discover(
+    reloader: &Mutex<ConfigReloader>,
+    outputs: &Outputs,
+) -> Result<Vec<Monitor>, Error> {
+    let (line, timeout) = reloader.lock().unwrap().monitor_command()?;
+    let mut command = line.single("monitor_command")?;
+    let monitors = parse(&read_output(&mut command, timeout)?)?;
+
+    for mut state in outputs.lock(None)? {
+        state.set_monitors(&monitors);
+        // Errors are logged by the worker
+        state.take_pending();
+    }
+    Ok(monitors)
+}
+
+// Thread: Notice monitors being plugged in
+pub fn watch_monitors(reloader: Arc<Mutex<ConfigReloader>>, outputs: Arc<Outputs>) {
+    loop {
+        if let Err(err) = discover(&reloader, &outputs) {
+            error!("Couldn't discover the monitors: {err}");
+        }
+        let mut locked = reloader.lock().unwrap();
+        let Some(interval) = locked.monitor_poll_interval() else {
+            info!("Stopped polling the monitors");
+            locked.stopped_polling();
+            return;
+        };
+        drop(locked);
+        sleep(interval);
+    }
+}
@@ -0,0 +1,112 @@
+//! Order of the images in linear mode
+use std::{
+    cmp::Ordering,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+/// What linear mode sorts the images by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// File names, with numbers compared by value so `2.png` comes before `10.png`
+    #[default]
+    Name,
+    /// Modification time
+    Modified,
+    /// Creation time, if the filesystem records it
+    Created,
+    /// File size
+    Size,
+}
+
+/// Sort key of an image, images without metadata come first
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SortKey {
+    primary: u128,
+    name: Vec<Chunk>,
+    path: PathBuf,
+}
+
+/// Part of a file name, numbers sort before text
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Chunk {
+    /// Digits without leading zeros, compared by length first
+    Number(usize, String),
+    /// Compared ignoring case
+    Text(String),
+}
+
+impl Order {
+    /// Sort key of `entry`, whose metadata is read from `file`
+    /// They differ for image sets, which are sorted by one of their images
+    pub fn key(&self, entry: &Path, file: &Path) -> SortKey {
+        let metadata = || fs::metadata(file).ok();
+        let nanos = |time: Option<SystemTime>| {
+            time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |duration| duration.as_nanos())
+        };
+        let primary = match self {
+            Order::Name => 0,
+            Order::Modified => nanos(metadata().and_then(|m| m.modified().ok())),
+            Order::Created => nanos(metadata().and_then(|m| m.created().ok())),
+            Order::Size => metadata().map_or(0, |m| u128::from(m.len())),
+        };
+        SortKey {
+            primary,
+            name: natural(&entry.to_string_lossy()),
+            path: entry.to_path_buf(),
+        }
+    }
+}
+
+/// Split a name into numbers and text
+fn natural(name: &str) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut rest = name;
+    while let Some(first) = rest.chars().next() {
+        let digits = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != digits)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(if digits {
+            let number = chunk.trim_start_matches('0');
+            Chunk::Number(number.len(), number.to_owned())
+        } else {
+            Chunk::Text(chunk.to_lowercase())
+        });
+        rest = tail;
+    }
+    chunks
+}
+
+/// `ordering`, or its reverse when sorting in descending order
+pub fn direction(ordering: Ordering, descending: bool) -> Ordering {
+    if descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert!(natural("2.png") < natural("10.png"));
+        assert!(natural("img9.png") < natural("img10.png"));
+        assert!(natural("img010.png") > natural("img9.png"));
+    }
+
+    #[test]
+    fn text_ignores_case() {
+        assert!(natural("apple.png") < natural("Banana.png"));
+        assert_eq!(natural("IMG1.png"), natural("img1.png"));
+    }
+}
@@ -0,0 +1,51 @@
+//! One [`State`] per output, each rotating its own wallpapers
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error::Error;
+use crate::state::State;
+
+#[derive(Debug)]
+pub struct Output {
+    /// Not set when all outputs share one rotation
+    pub name: Option<String>,
+    pub state: Arc<Mutex<State>>,
+}
+
+/// All outputs in the order of the config
+#[derive(Debug)]
+pub struct Outputs {
+    outputs: Vec<Output>,
+}
+
+impl Outputs {
+    pub fn new(outputs: Vec<Output>) -> Self {
+        Outputs { outputs }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.iter()
+    }
+
+    /// The output called `name`, or all outputs if no name is given
+    pub fn select(&self, name: Option<&str>) -> Result<Vec<&Output>, Error> {
+        let Some(name) = name else {
+            return Ok(self.outputs.iter().collect());
+        };
+        self.outputs
+            .iter()
+            .find(|output| output.name.as_deref() == Some(name))
+            .map(|output| vec![output])
+            .ok_or_else(|| Error::UnknownOutput(name.to_owned()))
+    }
+
+    /// Lock the states of the selected outputs
+    ///
+    /// States are always locked in the same order, so holding several at once can't deadlock.
+    pub fn lock(&self, name: Option<&str>) -> Result<Vec<MutexGuard<'_, State>>, Error> {
+        Ok(self
+            .select(name)?
+            .into_iter()
+            .map(|output| output.state.lock().unwrap())
+            .collect())
+    }
+}
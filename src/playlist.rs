@@ -0,0 +1,46 @@
+//! Playlist files listing images in the order they are shown
+//!
+//! Every line is a path, relative paths start at the directory of the playlist.
+//! Empty lines and lines starting with `#` are ignored, so M3U files work as well.
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::state::{expand_home, write_atomic};
+
+/// The images listed in a playlist, in order
+pub fn read(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let base = path.parent().unwrap_or(Path::new("/"));
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| normalize(&base.join(expand_home(PathBuf::from(line)))))
+        .collect())
+}
+
+/// Remove `.` and `..` without resolving symlinks, so entries compare equal to indexed paths
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+/// Write the images to a playlist, one absolute path per line
+pub fn write(path: &Path, images: &[PathBuf]) -> io::Result<()> {
+    let mut contents = String::new();
+    for image in images {
+        contents.push_str(&image.to_string_lossy());
+        contents.push('\n');
+    }
+    write_atomic(path, contents.as_bytes())
+}
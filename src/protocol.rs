@@ -0,0 +1,330 @@
+//! Messages exchanged between `wp` and the daemon
+//!
+//! Every message is a JSON document prefixed with its length as a big-endian `u32`.
+//! A connection starts with the client sending a [`Hello`], which the daemon answers
+//! with a [`Response`]. Afterwards the client sends one [`Request`] in an [`Envelope`]
+//! and receives one [`Response`].
+//!
+//! After answering [`Request::Watch`] the daemon keeps the connection open and writes
+//! one [`Event`] per line as plain newline-delimited JSON, without length prefix.
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{command::GetArgs, error::Error, library::List, state::NextImage, stats::Report};
+
+/// Version of the protocol, bumped on every incompatible change
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Refuse frames larger than this to avoid allocating arbitrary amounts of memory
+const MAX_FRAME_SIZE: u32 = 1 << 20;
+
+/// First message of every connection
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Hello {
+    pub version: u32,
+}
+
+/// A request and the output it is meant for
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    /// All outputs if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum Request {
+    Next,
+    Previous,
+    Stop,
+    Fallback,
+    Mode {
+        mode: NextImage,
+        image: Option<PathBuf>,
+    },
+    WpDir {
+        path: PathBuf,
+    },
+    Interval {
+        seconds: u64,
+    },
+    Get {
+        what: GetArgs,
+    },
+    /// Subscribe to [`Event`]s
+    Watch,
+    Status,
+    /// Read the config file again
+    Reload,
+    /// Run the monitor command again
+    Monitors,
+    /// Enable or disable a source
+    Source {
+        name: String,
+        enabled: bool,
+    },
+    /// Write the candidates or the history to a playlist
+    SavePlaylist {
+        path: PathBuf,
+        history: bool,
+    },
+    /// Rate the current image
+    Rate {
+        stars: u8,
+    },
+    /// Add an image to a list or remove it, the current image if not given
+    Mark {
+        list: List,
+        image: Option<PathBuf>,
+        marked: bool,
+    },
+    /// The images in a list, one per line
+    List {
+        list: List,
+    },
+    /// Summary of the display statistics
+    Stats {
+        /// Length of the rankings
+        top: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum Response {
+    Ok,
+    Value {
+        value: String,
+    },
+    /// One status per output
+    Status {
+        outputs: Vec<Status>,
+    },
+    Stats {
+        stats: Report,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+/// Everything that can be queried about the daemon
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Status {
+    /// Not set when all outputs share one rotation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    pub wallpaper: PathBuf,
+    /// Interval in seconds
+    pub duration: u64,
+    /// Seconds until the next change
+    pub remaining: u64,
+    pub mode: NextImage,
+    pub fallback: bool,
+    pub wp_dir: PathBuf,
+    /// Number of candidate images in `wp_dir`
+    #[serde(default)]
+    pub images: usize,
+    /// Number of images in the history
+    pub history: usize,
+    /// Stars of the wallpaper, if it was rated
+    #[serde(default)]
+    pub rating: Option<u8>,
+}
+
+impl Status {
+    /// Placeholder names and their values, in display order
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let name = self
+            .wallpaper
+            .file_name()
+            .unwrap_or(self.wallpaper.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        vec![
+            ("output", self.output.clone().unwrap_or_default()),
+            ("wallpaper", self.wallpaper.to_string_lossy().into_owned()),
+            ("name", name),
+            ("mode", format!("{:?}", self.mode)),
+            ("duration", self.duration.to_string()),
+            ("remaining", self.remaining.to_string()),
+            ("fallback", self.fallback.to_string()),
+            ("wp_dir", self.wp_dir.to_string_lossy().into_owned()),
+            ("images", self.images.to_string()),
+            ("history", self.history.to_string()),
+            (
+                "rating",
+                self.rating
+                    .map(|stars| stars.to_string())
+                    .unwrap_or_default(),
+            ),
+        ]
+    }
+
+    /// Replace every `{field}` in the template with its value
+    pub fn format(&self, template: &str) -> Result<String, String> {
+        let fields = self.fields();
+        let mut output = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            let end = rest[start..].find('}').ok_or_else(|| {
+                format!(
+                    "Unclosed '{{' in format at {}",
+                    template.len() - rest.len() + start
+                )
+            })?;
+            let key = &rest[start + 1..start + end];
+            let (_, value) = fields
+                .iter()
+                .find(|(name, _)| *name == key)
+                .ok_or_else(|| format!("Unknown field {{{key}}} in format"))?;
+            output.push_str(value);
+            rest = &rest[start + end + 1..];
+        }
+        output.push_str(rest);
+        Ok(output)
+    }
+}
+
+/// Sent to subscribers whenever the state changes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
+    /// What changed
+    pub event: EventKind,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    Wallpaper,
+    Mode,
+    Fallback,
+    Duration,
+    WpDir,
+    /// A source was enabled or disabled
+    Sources,
+    /// The wallpaper was rated
+    Rating,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    /// Client and daemon speak different protocol versions
+    VersionMismatch,
+    /// The message couldn't be parsed
+    BadRequest,
+    NoImages,
+    InvalidPath,
+    /// The request isn't possible in the current state
+    InvalidState,
+    CommandFailed,
+    InvalidConfig,
+    UnknownOutput,
+    UnknownSource,
+    Io,
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = match self {
+            ErrorCode::VersionMismatch => "version-mismatch",
+            ErrorCode::BadRequest => "bad-request",
+            ErrorCode::NoImages => "no-images",
+            ErrorCode::InvalidPath => "invalid-path",
+            ErrorCode::InvalidState => "invalid-state",
+            ErrorCode::CommandFailed => "command-failed",
+            ErrorCode::InvalidConfig => "invalid-config",
+            ErrorCode::UnknownOutput => "unknown-output",
+            ErrorCode::UnknownSource => "unknown-source",
+            ErrorCode::Io => "io",
+        };
+        write!(f, "{code}")
+    }
+}
+
+impl From<Result<(), Error>> for Response {
+    fn from(result: Result<(), Error>) -> Self {
+        match result {
+            Ok(()) => Response::Ok,
+            Err(err) => err.into(),
+        }
+    }
+}
+
+impl From<Error> for Response {
+    fn from(err: Error) -> Self {
+        Response::Error {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Write a length prefixed JSON message
+pub fn write_message<T: Serialize>(mut writer: impl Write, message: &T) -> io::Result<()> {
+    let buffer = serde_json::to_vec(message)?;
+    let len = u32::try_from(buffer.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_SIZE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Message too large"))?;
+
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(&buffer)?;
+    writer.flush()
+}
+
+/// Read a length prefixed JSON message
+pub fn read_message<T: DeserializeOwned>(mut reader: impl Read) -> io::Result<T> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Message of {len} bytes exceeds the maximum size"),
+        ));
+    }
+
+    let mut buffer = vec![0; len as usize];
+    reader.read_exact(&mut buffer)?;
+    Ok(serde_json::from_slice(&buffer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &Hello { version: 3 }).unwrap();
+        let hello: Hello = read_message(&buffer[..]).unwrap();
+        assert_eq!(hello.version, 3);
+    }
+
+    #[test]
+    fn oversize_frames_are_rejected() {
+        let mut frame = (MAX_FRAME_SIZE + 1).to_be_bytes().to_vec();
+        frame.extend_from_slice(b"{}");
+        let err = read_message::<Hello>(&frame[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut buffer = Vec::new();
+        let err = write_message(&mut buffer, &"x".repeat(MAX_FRAME_SIZE as usize)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(buffer.is_empty());
+    }
+}
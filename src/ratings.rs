@@ -0,0 +1,162 @@
+//! Star ratings of images, stored by content hash so they survive renames
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::state::write_atomic;
+
+/// Ratings go from one to this many stars
+pub const MAX_STARS: u8 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Rating {
+    stars: u8,
+    /// Size of the image, only images of a rated size are hashed
+    size: u64,
+    /// Where the image was when it was rated
+    path: PathBuf,
+}
+
+/// Ratings of all outputs, saved in the state directory
+#[derive(Debug, Default)]
+pub struct Ratings {
+    file: Option<PathBuf>,
+    /// By SHA-256 of the contents
+    ratings: BTreeMap<String, Rating>,
+    sizes: HashSet<u64>,
+    /// Content hashes, valid as long as size and modification time don't change
+    hashes: HashMap<PathBuf, (u64, SystemTime, String)>,
+    /// Hashes of rated images where they were last seen, so weighted mode doesn't read any file
+    paths: HashMap<PathBuf, String>,
+}
+
+impl Ratings {
+    /// Read the ratings saved in `file`, no ratings if there is none
+    pub fn load(file: PathBuf) -> Self {
+        let ratings: BTreeMap<String, Rating> = match fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                error!(
+                    "Ignoring invalid ratings in {}: {err}",
+                    file.to_string_lossy()
+                );
+                BTreeMap::new()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                error!(
+                    "Couldn't read ratings from {}: {err}",
+                    file.to_string_lossy()
+                );
+                BTreeMap::new()
+            }
+        };
+        Ratings {
+            file: Some(file),
+            sizes: ratings.values().map(|rating| rating.size).collect(),
+            paths: ratings
+                .iter()
+                .map(|(hash, rating)| (rating.path.clone(), hash.clone()))
+                .collect(),
+            ratings,
+            hashes: HashMap::new(),
+        }
+    }
+
+    /// Stars of the image, `None` if it wasn't rated
+    pub fn get(&mut self, path: &Path) -> Option<u8> {
+        let size = fs::metadata(path).ok()?.len();
+        if !self.sizes.contains(&size) {
+            return None;
+        }
+        let hash = self.hash(path).ok()?;
+        let stars = self.ratings.get(&hash)?.stars;
+        self.paths.insert(path.to_path_buf(), hash);
+        Some(stars)
+    }
+
+    /// Rate the image with one to [`MAX_STARS`] stars
+    pub fn rate(&mut self, path: &Path, stars: u8) -> Result<(), Error> {
+        if !(1..=MAX_STARS).contains(&stars) {
+            return Err(Error::InvalidRating(stars));
+        }
+        if !path.is_file() {
+            return Err(Error::InvalidImage(path.to_path_buf()));
+        }
+        let hash = self.hash(path)?;
+        let size = fs::metadata(path)?.len();
+        info!("Rating {} with {stars} stars", path.to_string_lossy());
+        self.sizes.insert(size);
+        self.paths.insert(path.to_path_buf(), hash.clone());
+        self.ratings.insert(
+            hash,
+            Rating {
+                stars,
+                size,
+                path: path.to_path_buf(),
+            },
+        );
+        self.store();
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ratings.is_empty()
+    }
+
+    /// Whether the image may be a rated one which wasn't seen at this path
+    pub fn may_be_rated(&self, path: &Path, size: u64) -> bool {
+        self.sizes.contains(&size) && !self.paths.contains_key(path)
+    }
+
+    /// The image at `path` has the content hash `hash`, see [`crate::library::find_rated`]
+    pub fn found(&mut self, path: &Path, hash: String) {
+        if self.ratings.contains_key(&hash) {
+            debug!("Found the rated image {}", path.to_string_lossy());
+            self.paths.insert(path.to_path_buf(), hash);
+        }
+    }
+
+    /// Stars of the image if it was rated or found at this path, without reading it
+    pub fn known(&self, path: &Path) -> Option<u8> {
+        let hash = self.paths.get(path)?;
+        self.ratings.get(hash).map(|rating| rating.stars)
+    }
+
+    fn store(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if let Err(err) = write_atomic(file, &serde_json::to_vec(&self.ratings).unwrap()) {
+            error!("Couldn't save ratings to {}: {err}", file.to_string_lossy());
+        }
+    }
+
+    fn hash(&mut self, path: &Path) -> io::Result<String> {
+        let metadata = fs::metadata(path)?;
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        if let Some((s, m, hash)) = self.hashes.get(path) {
+            if (*s, *m) == (size, modified) {
+                return Ok(hash.clone());
+            }
+        }
+        let hash = hash_file(path)?;
+        self.hashes
+            .insert(path.to_path_buf(), (size, modified, hash.clone()));
+        Ok(hash)
+    }
+}
+
+/// SHA-256 of the contents of a file
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,246 @@
+use std::{
+    ffi::OsStr,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, sleep},
+    time::Duration,
+};
+
+use inotify::{Inotify, WatchMask};
+use log::{error, info};
+
+use crate::backend::{self, CommandLine, WallpaperCommands};
+use crate::config::Config;
+use crate::daemon::DaemonArgs;
+use crate::error::Error;
+use crate::monitors::watch_monitors;
+use crate::outputs::Outputs;
+use crate::state::{expand_home, State};
+
+/// Config the daemon currently runs with
+///
+/// Settings given on the command line take precedence and are never reloaded.
+pub struct ConfigReloader {
+    args: DaemonArgs,
+    file: PathBuf,
+    config: Config,
+    /// Whether the threads of [`watch_config`] and [`watch_monitors`] are running
+    watching: bool,
+    polling: bool,
+}
+
+impl ConfigReloader {
+    pub fn new(args: DaemonArgs, file: PathBuf, config: Config) -> Self {
+        ConfigReloader {
+            args,
+            file,
+            config,
+            watching: false,
+            polling: false,
+        }
+    }
+
+    /// The command listing the monitors and how long it may run
+    pub fn monitor_command(&self) -> Result<(CommandLine, Duration), Error> {
+        let command = self.config.monitor_command.clone().ok_or_else(|| {
+            Error::Config(format!(
+                "{}: monitor_command isn't set",
+                self.file.to_string_lossy()
+            ))
+        })?;
+        Ok((command, Duration::from_secs(self.config.command_timeout)))
+    }
+
+    /// How often to look for new monitors, `None` if only on request
+    pub fn monitor_poll_interval(&self) -> Option<Duration> {
+        Some(self.config.monitor_poll_interval)
+            .filter(|seconds| *seconds > 0 && self.config.monitor_command.is_some())
+            .map(Duration::from_secs)
+    }
+
+    /// Called by [`watch_monitors`] when it returns, so a reload can start it again
+    pub fn stopped_polling(&mut self) {
+        self.polling = false;
+    }
+
+    /// Read the config file and apply every setting which changed since the last load
+    /// If the config is invalid nothing is changed
+    pub fn reload(&mut self, states: &mut [MutexGuard<'_, State>]) -> Result<(), Error> {
+        info!("Reloading config from {}", self.file.to_string_lossy());
+        let config = Config::load(&self.file)?;
+        if config.output_names() != self.config.output_names() {
+            return Err(Error::Config(format!(
+                "{}: the outputs only change when restarting the daemon",
+                self.file.to_string_lossy()
+            )));
+        }
+        for state in states.iter() {
+            let (old, new) = self.for_output(&config, state.get_output());
+            let old = old.sources();
+            for (name, source) in new.sources() {
+                let changed = !old.iter().any(|(old_name, old_source)| {
+                    *old_name == name && old_source.directory == source.directory
+                });
+                if changed && !expand_home(source.directory.clone()).exists() {
+                    return Err(Error::InvalidDirectory(source.directory));
+                }
+            }
+        }
+
+        let backend_changed = config.backend != self.config.backend
+            || WallpaperCommands::new(&self.args, &config)
+                != WallpaperCommands::new(&self.args, &self.config);
+        for state in states.iter_mut() {
+            self.apply(&config, state, backend_changed)?;
+        }
+        self.config = config;
+        Ok(())
+    }
+
+    /// Old and new settings of an output, including the command line arguments
+    fn for_output(&self, config: &Config, output: Option<&str>) -> (Config, Config) {
+        (
+            self.config.for_output(output).with_args(&self.args),
+            config.for_output(output).with_args(&self.args),
+        )
+    }
+
+    /// Apply the settings of one output
+    fn apply(
+        &self,
+        config: &Config,
+        state: &mut State,
+        backend_changed: bool,
+    ) -> Result<(), Error> {
+        let (old, new) = self.for_output(config, state.get_output());
+
+        if backend_changed {
+            state.set_backend(backend::create(&self.args, &new));
+        }
+        if new.aspect_ratio != old.aspect_ratio
+            || new.aspect_ratio_tolerance != old.aspect_ratio_tolerance
+        {
+            state.set_aspect_ratio(new.aspect_ratio, new.aspect_ratio_tolerance);
+        }
+        let span_changed = new.span != old.span;
+        if span_changed {
+            state.set_span(new.span);
+        }
+        if new.layout != old.layout {
+            state.set_monitors(&new.layout());
+        }
+        if new.interval != old.interval {
+            state.change_interval(Duration::from_secs(new.interval));
+        }
+        if new.command_timeout != old.command_timeout {
+            state.set_command_timeout(Duration::from_secs(new.command_timeout));
+        }
+        if new.history_length != old.history_length {
+            state.set_history_length(new.history_length);
+        }
+        if new.default_image != old.default_image {
+            state.set_default_image(new.default_image.clone());
+        }
+        if new.ping_pong != old.ping_pong {
+            state.set_ping_pong(new.ping_pong);
+        }
+        if new.unrated_weight != old.unrated_weight {
+            state.set_unrated_weight(new.unrated_weight);
+        }
+        if new.selector_command != old.selector_command {
+            state.set_selector_command(new.selector_command.clone());
+        }
+        if new.mode != old.mode && !state.get_fallback() {
+            state.update_action(new.mode, None)?;
+        }
+        state.set_configured(&new);
+        // Last because they apply the wallpaper again
+        let sources_changed =
+            new.sources() != old.sources() || new.index_options() != old.index_options();
+        if sources_changed {
+            state.set_sources(new.sources(), new.index_options());
+        } else if backend_changed || span_changed {
+            state.update();
+        }
+        Ok(())
+    }
+}
+
+/// Start the threads the config enables which aren't running, at startup and after every reload
+pub fn start_threads(reloader: &Arc<Mutex<ConfigReloader>>, outputs: &Arc<Outputs>) {
+    let mut locked = reloader.lock().unwrap();
+    if locked.config.watch_config && !locked.watching {
+        locked.watching = true;
+        let (r, o) = (reloader.clone(), outputs.clone());
+        thread::spawn(move || watch_config(r, o));
+    }
+    // Without polling the monitors are discovered once, and again after every reload
+    if locked.config.monitor_command.is_some() && !locked.polling {
+        locked.polling = true;
+        let (r, o) = (reloader.clone(), outputs.clone());
+        thread::spawn(move || watch_monitors(r, o));
+    }
+}
+
+// Thread: Reload config when the file changes
+pub fn watch_config(reloader: Arc<Mutex<ConfigReloader>>, outputs: Arc<Outputs>) {
+    let stopped = || reloader.lock().unwrap().watching = false;
+    let file = reloader.lock().unwrap().file.clone();
+    let (Some(dir), Some(name)) = (file.parent(), file.file_name()) else {
+        error!("Can't watch config file {}", file.to_string_lossy());
+        stopped();
+        return;
+    };
+
+    let mut inotify = match Inotify::init() {
+        Ok(inotify) => inotify,
+        Err(err) => {
+            error!("Couldn't initialize inotify: {err}");
+            stopped();
+            return;
+        }
+    };
+    // Watch the directory, because editors replace the file instead of writing to it
+    let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE;
+    if let Err(err) = inotify.watches().add(dir, mask) {
+        error!("Couldn't watch {}: {err}", dir.to_string_lossy());
+        stopped();
+        return;
+    }
+    info!("Watching config file {}", file.to_string_lossy());
+
+    let mut buffer = [0; 4096];
+    loop {
+        let changed = match inotify.read_events_blocking(&mut buffer) {
+            Ok(events) => events
+                .into_iter()
+                .any(|event| event.name == Some(OsStr::new(name))),
+            Err(err) => {
+                error!("Couldn't read inotify events: {err}");
+                stopped();
+                return;
+            }
+        };
+        if !changed {
+            continue;
+        }
+
+        // Wait for the editor to finish writing
+        sleep(Duration::from_millis(100));
+        {
+            let mut locked = reloader.lock().unwrap();
+            if !locked.config.watch_config {
+                info!("Stopped watching config file {}", file.to_string_lossy());
+                locked.watching = false;
+                return;
+            }
+            let mut states = outputs.lock(None).expect("All outputs exist");
+            if let Err(err) = locked.reload(&mut states) {
+                error!("Keeping the old config: {err}");
+                continue;
+            }
+            states.iter().for_each(|state| state.store());
+        }
+        start_threads(&reloader, &outputs);
+    }
+}
@@ -0,0 +1,151 @@
+//! Which files in the wallpaper directory are candidate images
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+
+/// The `[scan]` table of the config
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ScanConfig {
+    /// How many levels of sub-directories to read, 0 only reads the wallpaper directory
+    pub max_depth: usize,
+    /// Enter directories which are symlinks, every directory is still read only once
+    pub follow_symlinks: bool,
+    /// Include files and directories starting with a dot
+    pub hidden: bool,
+    /// File extensions of images, ignoring case, any extension if empty
+    pub extensions: Vec<String>,
+    /// Check the first bytes of every file for a known image format
+    pub sniff: bool,
+    /// Only files matching one of these globs are images, all files if empty
+    pub include: Globs,
+    /// Files and directories matching one of these globs are skipped
+    pub exclude: Globs,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            max_depth: 0,
+            follow_symlinks: false,
+            hidden: false,
+            extensions: [
+                "png", "jpg", "jpeg", "webp", "gif", "bmp", "tif", "tiff", "avif", "heic", "jxl",
+            ]
+            .map(str::to_owned)
+            .to_vec(),
+            sniff: true,
+            include: Globs::default(),
+            exclude: Globs::default(),
+        }
+    }
+}
+
+impl ScanConfig {
+    /// Whether an entry is skipped because of its name, `relative` is relative to the wallpaper directory
+    pub fn skips(&self, relative: &Path) -> bool {
+        let hidden = relative
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        (hidden && !self.hidden) || self.exclude.is_match(relative)
+    }
+
+    /// Whether a file is a candidate image
+    pub fn accepts(&self, path: &Path, relative: &Path) -> bool {
+        if self.skips(relative) {
+            return false;
+        }
+        if !self.include.is_empty() && !self.include.is_match(relative) {
+            return false;
+        }
+        if !self.extensions.is_empty() {
+            let extension = path.extension().unwrap_or_default().to_string_lossy();
+            if !self.extensions.iter().any(|allowed| {
+                allowed
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(&extension)
+            }) {
+                return false;
+            }
+        }
+        !self.sniff || is_image(path)
+    }
+}
+
+/// Whether the file starts like an image
+fn is_image(path: &Path) -> bool {
+    let mut header = Vec::with_capacity(32);
+    let read = File::open(path).and_then(|file| file.take(32).read_to_end(&mut header));
+    read.is_ok() && imagesize::image_type(&header).is_ok()
+}
+
+/// Glob patterns, matched against the file name if they don't contain a `/`
+/// and against the path relative to the wallpaper directory otherwise
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct Globs {
+    patterns: Vec<String>,
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Globs {
+    fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    fn is_match(&self, relative: &Path) -> bool {
+        relative
+            .file_name()
+            .is_some_and(|name| self.names.is_match(name))
+            || self.paths.is_match(relative)
+    }
+}
+
+impl Default for Globs {
+    fn default() -> Self {
+        Globs {
+            patterns: Vec::new(),
+            names: GlobSet::empty(),
+            paths: GlobSet::empty(),
+        }
+    }
+}
+
+impl PartialEq for Globs {
+    fn eq(&self, other: &Self) -> bool {
+        self.patterns == other.patterns
+    }
+}
+
+impl TryFrom<Vec<String>> for Globs {
+    type Error = globset::Error;
+
+    fn try_from(patterns: Vec<String>) -> Result<Self, Self::Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in &patterns {
+            let glob = GlobBuilder::new(pattern).literal_separator(true).build()?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        Ok(Globs {
+            patterns,
+            names: names.build()?,
+            paths: paths.build()?,
+        })
+    }
+}
+
+/// `path` relative to `root`
+pub fn relative(root: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(root).unwrap_or(path).to_path_buf()
+}
@@ -0,0 +1,391 @@
+//! How the next image is chosen, every mode which changes the image has a [`Strategy`]
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::Debug,
+    path::PathBuf,
+    process::Command,
+    sync::Mutex,
+    time::Duration,
+};
+
+use log::debug;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    seq::SliceRandom,
+};
+use serde_json::json;
+
+use crate::backend::{communicate, CommandLine};
+use crate::bag::ShuffleBag;
+use crate::error::Error;
+use crate::library::Library;
+use crate::sources::Sources;
+use crate::state::NextImage;
+
+/// Everything a strategy may base its choice on
+pub struct Selection<'a> {
+    pub sources: &'a Sources,
+    /// Images with the aspect ratio of the output, `None` if it doesn't matter or no image has it
+    matching: Option<Vec<&'a PathBuf>>,
+    /// The same for quick lookups
+    matching_set: HashSet<&'a PathBuf>,
+    /// Whether other images may be chosen once every candidate was shown recently
+    prefer: bool,
+    /// Previously shown images, the current one last
+    pub history: &'a VecDeque<PathBuf>,
+    /// The same for quick lookups
+    recent: HashSet<&'a PathBuf>,
+    /// Images which are never chosen
+    banned: &'a HashSet<PathBuf>,
+    /// Ratings, favorites and banned images
+    pub library: &'a Mutex<Library>,
+}
+
+/// Random picks to try before collecting the fresh candidates
+const TRIES: usize = 16;
+
+impl<'a> Selection<'a> {
+    pub fn new(
+        sources: &'a Sources,
+        matching: Option<Vec<&'a PathBuf>>,
+        prefer: bool,
+        history: &'a VecDeque<PathBuf>,
+        banned: &'a HashSet<PathBuf>,
+        library: &'a Mutex<Library>,
+    ) -> Self {
+        Selection {
+            sources,
+            matching_set: matching.iter().flatten().copied().collect(),
+            matching,
+            prefer,
+            history,
+            recent: history.iter().collect(),
+            banned,
+            library,
+        }
+    }
+
+    fn current(&self) -> &'a PathBuf {
+        self.history.back().expect("The history is never empty")
+    }
+
+    /// Whether the image may be chosen
+    fn is_candidate(&self, image: &PathBuf) -> bool {
+        match self.matching {
+            Some(_) => self.matching_set.contains(image),
+            None => !self.banned.contains(image),
+        }
+    }
+
+    /// Images which may be chosen, in the order of the sources
+    pub fn candidates(&self) -> Vec<&'a PathBuf> {
+        match &self.matching {
+            Some(matching) => matching.clone(),
+            None => self.allowed().collect(),
+        }
+    }
+
+    /// Images of the active sources which aren't banned
+    fn allowed(&self) -> impl Iterator<Item = &'a PathBuf> + '_ {
+        self.sources
+            .images()
+            .into_iter()
+            .filter(|image| !self.banned.contains(*image))
+    }
+
+    /// Candidates which weren't shown recently
+    /// Without aspect ratio requirement other images may follow, all candidates once every one was shown
+    fn fresh(&self) -> Vec<&'a PathBuf> {
+        let candidates = self.candidates();
+        let mut choices: Vec<&PathBuf> = candidates
+            .iter()
+            .copied()
+            .filter(|image| !self.recent.contains(image))
+            .collect();
+        if choices.is_empty() && self.prefer {
+            choices = self
+                .allowed()
+                .filter(|image| !self.recent.contains(image))
+                .collect();
+        }
+        if choices.is_empty() {
+            choices = candidates;
+        }
+        choices
+    }
+
+    /// A candidate which wasn't shown recently from a source chosen by weight,
+    /// without going through every image
+    /// `None` if a few tries found none
+    fn random_fresh(&self) -> Option<&'a PathBuf> {
+        if self.matching.is_some() {
+            return None;
+        }
+        (0..TRIES)
+            .filter_map(|_| self.sources.random())
+            .find(|image| self.is_candidate(image) && !self.recent.contains(image))
+    }
+}
+
+/// Chooses the next image
+pub trait Strategy: Debug + Send {
+    /// The image to show next, there is at least one candidate
+    fn select(&mut self, selection: &Selection) -> Result<PathBuf, Error>;
+}
+
+/// The strategy of every mode
+#[derive(Debug)]
+pub struct Strategies {
+    pub random: Random,
+    pub weighted: Weighted,
+    pub least_recent: LeastRecent,
+    pub linear: Linear,
+    pub shuffle: Shuffle,
+    pub favorites: Favorites,
+    pub external: External,
+}
+
+impl Strategies {
+    /// The strategy of `mode`, `None` for static mode
+    pub fn get(&mut self, mode: NextImage) -> Option<&mut dyn Strategy> {
+        Some(match mode {
+            NextImage::Random => &mut self.random,
+            NextImage::Weighted => &mut self.weighted,
+            NextImage::Linear => &mut self.linear,
+            NextImage::Shuffle => &mut self.shuffle,
+            NextImage::Favorites => &mut self.favorites,
+            NextImage::LeastRecent => &mut self.least_recent,
+            NextImage::External => &mut self.external,
+            NextImage::Static => return None,
+        })
+    }
+}
+
+/// Any image which wasn't shown recently, choosing the source by weight
+#[derive(Debug, Default)]
+pub struct Random;
+
+impl Strategy for Random {
+    fn select(&mut self, selection: &Selection) -> Result<PathBuf, Error> {
+        let image = match selection.random_fresh() {
+            Some(image) => image,
+            None => selection.sources.choose(&selection.fresh()),
+        };
+        Ok(image.clone())
+    }
+}
+
+/// Like [`Random`], but images with more stars are chosen more often
+#[derive(Debug)]
+pub struct Weighted {
+    /// Weight of images without rating, a rated image weighs its number of stars
+    pub unrated_weight: f64,
+}
+
+impl Strategy for Weighted {
+    fn select(&mut self, selection: &Selection) -> Result<PathBuf, Error> {
+        let mut rated: Vec<(&PathBuf, u8)> = Vec::new();
+        let mut unrated: Vec<&PathBuf> = Vec::new();
+        {
+            let library = selection.library.lock().unwrap();
+            for image in selection.fresh() {
+                match library.ratings.known(image) {
+                    Some(stars) => rated.push((image, stars)),
+                    None => unrated.push(image),
+                }
+            }
+        }
+        // The unrated images weigh as much as all of them together, then one of them is chosen
+        let weights = rated
+            .iter()
+            .map(|(_, stars)| f64::from(*stars))
+            .chain([unrated.len() as f64 * self.unrated_weight]);
+        let mut rng = rand::thread_rng();
+        let image = match WeightedIndex::new(weights) {
+            Ok(distribution) => match rated.get(distribution.sample(&mut rng)) {
+                Some((image, _)) => image,
+                None => unrated
+                    .choose(&mut rng)
+                    .expect("Unrated images have weight"),
+            },
+            // Nothing is rated and unrated images weigh nothing
+            Err(_) => unrated
+                .choose(&mut rng)
+                .expect("There is at least one candidate"),
+        };
+        Ok(image.to_path_buf())
+    }
+}
+
+/// Images which weren't shown for the longest time, never shown ones first
+///
+/// Images last shown within the same hour count as equally old, one of them is chosen at random.
+#[derive(Debug, Default)]
+pub struct LeastRecent;
+
+/// Seconds in which images count as shown at the same time
+const TIE: u64 = 60 * 60;
+
+impl Strategy for LeastRecent {
+    fn select(&mut self, selection: &Selection) -> Result<PathBuf, Error> {
+        let choices = selection.fresh();
+        let library = selection.library.lock().unwrap();
+        let age = |image: &PathBuf| library.stats.last_shown(image) / TIE;
+        let oldest = choices
+            .iter()
+            .map(|image| age(image))
+            .min()
+            .expect("There is at least one candidate");
+        let oldest: Vec<&PathBuf> = choices
+            .into_iter()
+            .filter(|image| age(image) == oldest)
+            .collect();
+        Ok(oldest
+            .choose(&mut rand::thread_rng())
+            .expect("There is at least one candidate")
+            .to_path_buf())
+    }
+}
+
+/// The images in order, see [`crate::order`]
+#[derive(Debug, Default)]
+pub struct Linear {
+    /// Turn around at the ends instead of starting over
+    pub ping_pong: bool,
+    /// Going from the last image to the first one
+    pub backwards: bool,
+}
+
+impl Strategy for Linear {
+    fn select(&mut self, selection: &Selection) -> Result<PathBuf, Error> {
+        let mut image = selection.current().to_path_buf();
+        let mut backwards = self.backwards;
+        // Twice, since ping-pong passes every image on the way back
+        for _ in 0..2 * selection.sources.len() {
+            let Some((next, direction)) = selection.sources.step(&image, backwards, self.ping_pong)
+            else {
+                break;
+            };
+            (image, backwards) = (next.clone(), direction);
+            if selection.is_candidate(&image) {
+                self.backwards = backwards;
+                return Ok(image);
+            }
+        }
+        Ok(selection
+            .candidates()
+            .first()
+            .expect("There is at least one candidate")
+            .to_path_buf())
+    }
+}
+
+/// Every image once in random order, then the next round
+#[derive(Debug, Default)]
+pub struct Shuffle {
+    pub bag: ShuffleBag,
+}
+
+impl Strategy for Shuffle {
+    fn select(&mut self, selection: &Selection) -> Result<PathBuf, Error> {
+        Ok(self
+            .bag
+            .draw(&selection.candidates(), selection.current())
+            .expect("There is at least one candidate"))
+    }
+}
+
+/// Only favorites, every one once per round
+///
+/// Favorites don't have to be in the sources, so the aspect ratio isn't taken into account.
+#[derive(Debug, Default)]
+pub struct Favorites {
+    bag: ShuffleBag,
+}
+
+impl Strategy for Favorites {
+    fn select(&mut self, selection: &Selection) -> Result<PathBuf, Error> {
+        let library = selection.library.lock().unwrap();
+        let favorites: Vec<&PathBuf> = library
+            .favorites
+            .images()
+            .iter()
+            .filter(|image| !library.banned.contains(image) && image.is_file())
+            .collect();
+        self.bag
+            .draw(&favorites, selection.current())
+            .ok_or(Error::NoFavorites)
+    }
+}
+
+/// Asks a program, which gets the candidates and the history as JSON on stdin
+/// and prints the path of the image to show
+///
+/// The program runs without holding any lock, see [`External::query`].
+#[derive(Debug, Default)]
+pub struct External {
+    pub command: Option<CommandLine>,
+}
+
+impl External {
+    /// What to ask the program for the next image of `output`
+    pub fn query(
+        &self,
+        selection: &Selection,
+        output: Option<&str>,
+        timeout: Duration,
+    ) -> Result<SelectorQuery, Error> {
+        let command = self
+            .command
+            .as_ref()
+            .ok_or_else(|| Error::Command("selector_command isn't set".to_owned()))?
+            .single("selector_command")?;
+        let input = json!({
+            "output": output,
+            "current": selection.current(),
+            "candidates": selection.candidates(),
+            "history": selection.history,
+        });
+        Ok(SelectorQuery {
+            command,
+            input: input.to_string().into_bytes(),
+            timeout,
+        })
+    }
+}
+
+impl Strategy for External {
+    /// Only `wp next` and the interval ask the program, other changes like banning the
+    /// current image choose at random
+    fn select(&mut self, selection: &Selection) -> Result<PathBuf, Error> {
+        debug!("Not asking the selector command, choosing at random");
+        Random.select(selection)
+    }
+}
+
+/// A question for the selector command of external mode
+#[derive(Debug)]
+pub struct SelectorQuery {
+    command: Command,
+    input: Vec<u8>,
+    timeout: Duration,
+}
+
+impl SelectorQuery {
+    /// Run the program, returns the image it chose
+    pub fn run(mut self) -> Result<PathBuf, Error> {
+        let command = &mut self.command;
+        let output = communicate(command, self.input, self.timeout)?;
+        let image = output
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .ok_or_else(|| Error::Command(format!("{command:?} didn't print an image")))?;
+        if !image.is_file() {
+            return Err(Error::InvalidImage(image));
+        }
+        debug!("{command:?} chose {}", image.to_string_lossy());
+        Ok(image)
+    }
+}
@@ -0,0 +1,134 @@
+//! Groups of images shown together, one image per output
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+/// The `[sets]` table of the config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Default)]
+#[serde(default)]
+pub struct SetsConfig {
+    /// Files whose name matches form sets, like `{set}-{role}` for `forest-left.png`
+    pub pattern: Option<Pattern>,
+    /// Every sub-directory is a set, the names of the files in it are the roles
+    pub folders: bool,
+    /// Output of every role, roles which aren't listed are used as output name
+    pub outputs: BTreeMap<String, String>,
+}
+
+impl SetsConfig {
+    /// The set a file in the wallpaper directory belongs to, and its role in it
+    pub fn classify(&self, path: &Path) -> Option<(PathBuf, String)> {
+        let stem = path.file_stem()?.to_str()?;
+        let (set, role) = self.pattern.as_ref()?.matches(stem)?;
+        Some((path.with_file_name(set), role.to_owned()))
+    }
+
+    pub fn output(&self, role: &str) -> String {
+        self.outputs
+            .get(role)
+            .cloned()
+            .unwrap_or_else(|| role.to_owned())
+    }
+}
+
+/// A file name pattern with a `{set}` and a `{role}` placeholder
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern {
+    prefix: String,
+    /// Whether `{set}` comes before `{role}`
+    set_first: bool,
+    separator: String,
+    suffix: String,
+}
+
+impl Pattern {
+    /// The set name and role of a file name without extension
+    fn matches<'a>(&self, name: &'a str) -> Option<(&'a str, &'a str)> {
+        let rest = name
+            .strip_prefix(&self.prefix)?
+            .strip_suffix(&self.suffix)?;
+        // Set names may contain the separator, roles usually don't
+        let (first, second) = if self.set_first {
+            rest.rsplit_once(&self.separator)?
+        } else {
+            rest.split_once(&self.separator)?
+        };
+        if first.is_empty() || second.is_empty() {
+            return None;
+        }
+        if self.set_first {
+            Some((first, second))
+        } else {
+            Some((second, first))
+        }
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        let (Some(set), Some(role)) = (pattern.find("{set}"), pattern.find("{role}")) else {
+            return Err(format!(
+                "'{pattern}' needs a {{set}} and a {{role}} placeholder"
+            ));
+        };
+        let set_first = set < role;
+        let (first, second) = if set_first {
+            (set..set + 5, role..role + 6)
+        } else {
+            (role..role + 6, set..set + 5)
+        };
+        if first.end > second.start {
+            return Err(format!("'{pattern}' has overlapping placeholders"));
+        }
+        let separator = &pattern[first.end..second.start];
+        if separator.is_empty() {
+            return Err(format!(
+                "'{pattern}' needs something between {{set}} and {{role}}"
+            ));
+        }
+        Ok(Pattern {
+            prefix: pattern[..first.start].to_owned(),
+            set_first,
+            separator: separator.to_owned(),
+            suffix: pattern[second.end..].to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn role_before_set() {
+        let pattern = Pattern::try_from("{role}_{set}".to_owned()).unwrap();
+        assert_eq!(
+            pattern.matches("left_beach_2024"),
+            Some(("beach_2024", "left"))
+        );
+        assert_eq!(pattern.matches("left_"), None);
+        assert_eq!(pattern.matches("beach"), None);
+    }
+
+    #[test]
+    fn set_before_role() {
+        let pattern = Pattern::try_from("pano-{set}.{role}".to_owned()).unwrap();
+        assert_eq!(
+            pattern.matches("pano-my.beach.left"),
+            Some(("my.beach", "left"))
+        );
+        assert_eq!(pattern.matches("beach.left"), None);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(Pattern::try_from("{set}".to_owned()).is_err());
+        assert!(Pattern::try_from("{set}{role}".to_owned()).is_err());
+    }
+}
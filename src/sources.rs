@@ -0,0 +1,502 @@
+//! Directories images are chosen from, each with its own weight and schedule
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use inotify::Watches;
+use log::{debug, warn};
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::index::{ImageIndex, IndexOptions};
+use crate::scan::ScanConfig;
+use crate::sets::SetsConfig;
+use crate::state::expand_home;
+
+/// Name of the source used when only a wallpaper directory is configured
+pub const DEFAULT_SOURCE: &str = "default";
+
+/// A `[sources.<name>]` table
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SourceConfig {
+    /// Directory with images, or a playlist listing them
+    pub directory: PathBuf,
+    /// How often random mode chooses this source compared to the others
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    /// Scan rules for this directory, the `[scan]` table if not set
+    pub scan: Option<ScanConfig>,
+    /// Times of day like "08:00-18:00" when the source is used, always if empty
+    #[serde(default)]
+    pub schedule: Vec<TimeRange>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl SourceConfig {
+    /// Source for a single wallpaper directory
+    pub fn new(directory: PathBuf) -> Self {
+        SourceConfig {
+            directory,
+            weight: default_weight(),
+            scan: None,
+            schedule: Vec::new(),
+            enabled: default_enabled(),
+        }
+    }
+}
+
+/// Part of the day between two times, wrapping around midnight if it ends before it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeRange {
+    /// Minutes since midnight
+    start: u32,
+    end: u32,
+}
+
+impl TimeRange {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl TryFrom<String> for TimeRange {
+    type Error = String;
+
+    fn try_from(range: String) -> Result<Self, Self::Error> {
+        let minutes = |time: &str| {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+            (hours <= 24 && minutes < 60 && hours * 60 + minutes <= 24 * 60)
+                .then_some(hours * 60 + minutes)
+        };
+        range
+            .split_once('-')
+            .and_then(|(start, end)| {
+                Some(TimeRange {
+                    start: minutes(start)?,
+                    end: minutes(end)?,
+                })
+            })
+            .ok_or_else(|| format!("'{range}' isn't a time range like 08:00-18:00"))
+    }
+}
+
+/// Minutes since midnight in local time
+fn minute_of_day() -> u32 {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut local) }.is_null() {
+        warn!("Couldn't read the local time");
+        return 0;
+    }
+    (local.tm_hour * 60 + local.tm_min) as u32
+}
+
+#[derive(Debug)]
+struct Source {
+    name: String,
+    config: SourceConfig,
+    /// Changed at runtime through `wp source`
+    enabled: bool,
+    index: ImageIndex,
+}
+
+impl Source {
+    fn scheduled(&self, minute: u32) -> bool {
+        self.config.schedule.is_empty()
+            || self
+                .config
+                .schedule
+                .iter()
+                .any(|range| range.contains(minute))
+    }
+}
+
+/// All sources of a state, in the order of their names
+#[derive(Debug, Default)]
+pub struct Sources {
+    sources: Vec<Source>,
+    /// Scan rules of sources without their own, and the image sets
+    options: IndexOptions,
+    watches: Option<Watches>,
+}
+
+impl Sources {
+    /// Index every source, directories which can't be read have no images
+    pub fn build(configs: Vec<(String, SourceConfig)>, options: IndexOptions) -> Self {
+        let mut sources = Sources::default();
+        sources.configure(configs, options);
+        sources
+    }
+
+    /// Use other sources, only indexing those which changed
+    pub fn configure(&mut self, configs: Vec<(String, SourceConfig)>, options: IndexOptions) {
+        let mut old: BTreeMap<String, Source> = self
+            .sources
+            .drain(..)
+            .map(|source| (source.name.clone(), source))
+            .collect();
+        for (name, config) in configs {
+            let index_options = IndexOptions {
+                scan: config.scan.clone().unwrap_or_else(|| options.scan.clone()),
+                ..options.clone()
+            };
+            let enabled = match old.get(&name) {
+                Some(source) if source.config.enabled == config.enabled => source.enabled,
+                _ => config.enabled,
+            };
+            let index = match old.remove(&name) {
+                Some(source)
+                    if source.config.directory == config.directory
+                        && *source.index.options() == index_options =>
+                {
+                    source.index
+                }
+                mut source => {
+                    if let Some(source) = &mut source {
+                        source.index.take_watches();
+                    }
+                    self.index(&config.directory, index_options)
+                }
+            };
+            self.sources.push(Source {
+                name,
+                config,
+                enabled,
+                index,
+            });
+        }
+        for source in old.values_mut() {
+            source.index.take_watches();
+        }
+        self.options = options;
+    }
+
+    fn index(&self, dir: &Path, options: IndexOptions) -> ImageIndex {
+        let dir = expand_home(dir.to_path_buf());
+        let mut index = ImageIndex::build(&dir, options.clone()).unwrap_or_else(|err| {
+            warn!("{err}");
+            ImageIndex::empty(&dir, options)
+        });
+        if let Some(watches) = &self.watches {
+            index.watch(watches.clone());
+        }
+        index
+    }
+
+    /// Name and config of every source
+    pub fn configs(&self) -> Vec<(String, SourceConfig)> {
+        self.sources
+            .iter()
+            .map(|source| (source.name.clone(), source.config.clone()))
+            .collect()
+    }
+
+    pub fn options(&self) -> &IndexOptions {
+        &self.options
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), Error> {
+        let source = self
+            .sources
+            .iter_mut()
+            .find(|source| source.name == name)
+            .ok_or_else(|| Error::UnknownSource(name.to_owned()))?;
+        source.enabled = enabled;
+        Ok(())
+    }
+
+    /// Names of the sources which are disabled
+    pub fn disabled(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .filter(|source| !source.enabled)
+            .map(|source| source.name.clone())
+            .collect()
+    }
+
+    /// Directory of the first source
+    pub fn dir(&self) -> &PathBuf {
+        self.sources[0].index.dir()
+    }
+
+    /// One line per source
+    pub fn describe(&self) -> Vec<String> {
+        let minute = minute_of_day();
+        self.sources
+            .iter()
+            .map(|source| {
+                let mut line = format!(
+                    "{}: {}, weight {}, {} {}",
+                    source.name,
+                    source.index.dir().to_string_lossy(),
+                    source.config.weight,
+                    source.index.len(),
+                    source.index.kind()
+                );
+                if !source.enabled {
+                    line.push_str(", disabled");
+                } else if !source.scheduled(minute) {
+                    line.push_str(", outside its schedule");
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// Directory or playlist of the first source containing `path`
+    pub fn dir_of(&self, path: &Path) -> Option<&PathBuf> {
+        self.sources
+            .iter()
+            .find(|source| source.index.contains(path))
+            .map(|source| source.index.dir())
+    }
+
+    /// Directory and number of images of every source
+    pub fn describe_dirs(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .map(|source| {
+                format!(
+                    "{} ({} {})",
+                    source.index.dir().to_string_lossy(),
+                    source.index.len(),
+                    source.index.kind()
+                )
+            })
+            .collect()
+    }
+
+    /// Enabled sources which are scheduled right now
+    /// Outside of every schedule all enabled sources are used
+    fn active(&self) -> Vec<&Source> {
+        let minute = minute_of_day();
+        let enabled = self.sources.iter().filter(|source| source.enabled);
+        let scheduled: Vec<&Source> = enabled
+            .clone()
+            .filter(|source| source.scheduled(minute))
+            .collect();
+        if scheduled.iter().any(|source| source.index.len() > 0) {
+            scheduled
+        } else {
+            debug!("No scheduled source has images, using all enabled sources");
+            enabled.collect()
+        }
+    }
+
+    /// Number of images in the active sources
+    pub fn len(&self) -> usize {
+        self.active().iter().map(|source| source.index.len()).sum()
+    }
+
+    /// Active sources which have images
+    fn populated(&self) -> Vec<&Source> {
+        self.active()
+            .into_iter()
+            .filter(|source| source.index.len() > 0)
+            .collect()
+    }
+
+    /// Images of the active sources
+    pub fn images(&self) -> Vec<&PathBuf> {
+        self.active()
+            .into_iter()
+            .flat_map(|source| source.index.images())
+            .collect()
+    }
+
+    /// Whether an active source has the image
+    pub fn contains(&self, path: &Path) -> bool {
+        self.active()
+            .iter()
+            .any(|source| source.index.contains(path))
+    }
+
+    /// The image after `path` in the active sources, or before it when going `backwards`
+    ///
+    /// Wraps around at the ends, or turns around with `ping_pong`, and returns the new direction.
+    /// Starts at the first image if `path` isn't indexed.
+    pub fn step(&self, path: &Path, backwards: bool, ping_pong: bool) -> Option<(&PathBuf, bool)> {
+        let sources = self.populated();
+        let last = sources
+            .iter()
+            .map(|source| source.index.len())
+            .sum::<usize>()
+            .checked_sub(1)?;
+        let mut offset = 0;
+        let position = sources.iter().find_map(|source| {
+            let position = source.index.position(path).map(|idx| offset + idx);
+            offset += source.index.len();
+            position
+        });
+        let (next, backwards) = match position {
+            Some(idx) => step_index(idx, last, backwards, ping_pong),
+            None => (0, false),
+        };
+        Some((nth(&sources, next), backwards))
+    }
+
+    /// A random image from a source chosen by weight
+    pub fn random(&self) -> Option<&PathBuf> {
+        let sources = self.populated();
+        let weights = sources.iter().map(|source| source.config.weight.max(0.0));
+        let mut rng = rand::thread_rng();
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => sources[distribution.sample(&mut rng)].index.random(),
+            // Every weight is zero
+            Err(_) => {
+                let len = sources.iter().map(|source| source.index.len()).sum();
+                (len > 0).then(|| nth(&sources, rng.gen_range(0..len)))
+            }
+        }
+    }
+
+    /// Choose a source by weight, then one of its candidates
+    pub fn choose<'a>(&self, candidates: &[&'a PathBuf]) -> &'a PathBuf {
+        let mut rng = rand::thread_rng();
+        let sources = self.populated();
+        let mut by_source: Vec<Vec<&'a PathBuf>> = vec![Vec::new(); sources.len()];
+        for candidate in candidates {
+            if let Some(idx) = sources
+                .iter()
+                .position(|source| source.index.contains(candidate))
+            {
+                by_source[idx].push(candidate);
+            }
+        }
+        let weights = sources.iter().zip(&by_source).map(|(source, images)| {
+            if images.is_empty() {
+                0.0
+            } else {
+                source.config.weight.max(0.0)
+            }
+        });
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => {
+                let images = &by_source[distribution.sample(&mut rng)];
+                images[rng.gen_range(0..images.len())]
+            }
+            // Every weight is zero
+            Err(_) => candidates[rng.gen_range(0..candidates.len())],
+        }
+    }
+
+    /// Image sets are configured for all sources together
+    pub fn sets(&self) -> Option<&SetsConfig> {
+        self.options.sets.as_ref()
+    }
+
+    /// The images of a set by role, `None` if `path` isn't a set
+    pub fn members(&self, path: &Path) -> Option<&BTreeMap<String, PathBuf>> {
+        self.sources
+            .iter()
+            .find_map(|source| source.index.members(path))
+    }
+
+    /// Width divided by height of an image, `None` if it isn't known
+    pub fn aspect_ratio(&self, path: &Path) -> Option<f64> {
+        self.sources
+            .iter()
+            .find(|source| source.index.contains(path))?
+            .index
+            .aspect_ratio(path)
+    }
+
+    /// Keep every index up to date through the given inotify instance
+    pub fn watch(&mut self, watches: Watches) {
+        for source in &mut self.sources {
+            source.index.watch(watches.clone());
+        }
+        self.watches = Some(watches);
+    }
+
+    pub fn indexes_mut(&mut self) -> impl Iterator<Item = &mut ImageIndex> {
+        self.sources.iter_mut().map(|source| &mut source.index)
+    }
+}
+
+/// The image at `idx` of all images of `sources` one after another
+fn nth<'a>(sources: &[&'a Source], mut idx: usize) -> &'a PathBuf {
+    for source in sources {
+        match source.index.images().get(idx) {
+            Some(image) => return image,
+            None => idx -= source.index.len(),
+        }
+    }
+    panic!("Index out of range")
+}
+
+/// Position after `idx` in a list ending at `last`, see [`Sources::step`]
+fn step_index(idx: usize, last: usize, backwards: bool, ping_pong: bool) -> (usize, bool) {
+    let next = if backwards {
+        idx.checked_sub(1)
+    } else {
+        Some(idx + 1).filter(|next| *next <= last)
+    };
+    match next {
+        Some(next) => (next, backwards),
+        None if last == 0 => (0, backwards),
+        None if ping_pong => (if backwards { 1 } else { last - 1 }, !backwards),
+        None => (if backwards { last } else { 0 }, backwards),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_wraps_around() {
+        assert_eq!(step_index(1, 2, false, false), (2, false));
+        assert_eq!(step_index(2, 2, false, false), (0, false));
+        assert_eq!(step_index(0, 2, true, false), (2, true));
+        assert_eq!(step_index(1, 1, false, false), (0, false));
+        assert_eq!(step_index(0, 1, true, false), (1, true));
+        assert_eq!(step_index(0, 0, false, false), (0, false));
+        assert_eq!(step_index(0, 0, true, false), (0, true));
+    }
+
+    #[test]
+    fn ping_pong_turns_around() {
+        assert_eq!(step_index(2, 2, false, true), (1, true));
+        assert_eq!(step_index(0, 2, true, true), (1, false));
+        assert_eq!(step_index(1, 1, false, true), (0, true));
+        assert_eq!(step_index(0, 1, true, true), (1, false));
+        assert_eq!(step_index(0, 0, false, true), (0, false));
+        assert_eq!(step_index(0, 0, true, true), (0, true));
+    }
+
+    #[test]
+    fn time_range_crosses_midnight() {
+        let range = TimeRange::try_from("22:00-06:30".to_owned()).unwrap();
+        assert!(range.contains(22 * 60));
+        assert!(range.contains(0));
+        assert!(range.contains(6 * 60 + 29));
+        assert!(!range.contains(6 * 60 + 30));
+        assert!(!range.contains(12 * 60));
+        assert!(!range.contains(21 * 60 + 59));
+    }
+
+    #[test]
+    fn invalid_time_ranges() {
+        assert!(TimeRange::try_from("25:00-06:00".to_owned()).is_err());
+        assert!(TimeRange::try_from("08:60-18:00".to_owned()).is_err());
+        assert!(TimeRange::try_from("08:00".to_owned()).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Panoramas spanning all monitors, every output shows its own slice of the image
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+};
+
+use image::{DynamicImage, ImageError, ImageFormat};
+use log::{debug, warn};
+
+use crate::backend::Job;
+use crate::daemon;
+use crate::error::Error;
+use crate::monitors::Monitor;
+use crate::state::write_atomic;
+
+/// Slices kept in the cache, older ones are deleted
+const MAX_CACHED_SLICES: usize = 64;
+
+/// Area in layout coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    fn hash_bits(&self, state: &mut impl Hasher) {
+        for value in [self.x, self.y, self.width, self.height] {
+            value.to_bits().hash(state);
+        }
+    }
+}
+
+/// Where an output is in the layout of all outputs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slice {
+    output: Rect,
+    /// Bounding box of all outputs
+    layout: Rect,
+}
+
+impl Slice {
+    /// Pixels of an image with the given size which are shown on the output
+    ///
+    /// The image is scaled to cover the whole layout and centered, cutting off what doesn't fit.
+    fn region(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (width, height) = (f64::from(width), f64::from(height));
+        let scale = (width / self.layout.width).min(height / self.layout.height);
+        let left = (width - self.layout.width * scale) / 2.0;
+        let top = (height - self.layout.height * scale) / 2.0;
+
+        let x = left + (self.output.x - self.layout.x) * scale;
+        let y = top + (self.output.y - self.layout.y) * scale;
+        (
+            x.round() as u32,
+            y.round() as u32,
+            (self.output.width * scale).round().max(1.0) as u32,
+            (self.output.height * scale).round().max(1.0) as u32,
+        )
+    }
+}
+
+/// Area of every monitor, positions are in logical pixels
+fn areas(monitors: &[Monitor]) -> Vec<Rect> {
+    monitors
+        .iter()
+        .map(|monitor| {
+            let (width, height) = monitor.size();
+            Rect {
+                x: f64::from(monitor.x),
+                y: f64::from(monitor.y),
+                width: f64::from(width) / monitor.scale,
+                height: f64::from(height) / monitor.scale,
+            }
+        })
+        .collect()
+}
+
+/// Bounding box of all monitors
+pub fn layout(monitors: &[Monitor]) -> Rect {
+    let areas = areas(monitors);
+    let left = areas.iter().map(|r| r.x).fold(f64::INFINITY, f64::min);
+    let top = areas.iter().map(|r| r.y).fold(f64::INFINITY, f64::min);
+    let right = areas
+        .iter()
+        .map(|r| r.x + r.width)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let bottom = areas
+        .iter()
+        .map(|r| r.y + r.height)
+        .fold(f64::NEG_INFINITY, f64::max);
+    Rect {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    }
+}
+
+/// The slice of every monitor, in the same order
+pub fn slices(monitors: &[Monitor]) -> Vec<Slice> {
+    let layout = layout(monitors);
+    areas(monitors)
+        .into_iter()
+        .map(|output| Slice { output, layout })
+        .collect()
+}
+
+/// Replace the image of every job with a slice by the cut out slice
+///
+/// Slices are cached, so an image is only decoded the first time it is shown.
+pub fn cut(jobs: &mut [Job]) -> Result<(), Error> {
+    let mut decoded: Option<(PathBuf, DynamicImage)> = None;
+    let mut written = false;
+    for job in jobs.iter_mut() {
+        let Some(slice) = job.slice.take() else {
+            continue;
+        };
+        let path = cache_path(&job.image, &slice)?;
+        if !path.is_file() {
+            if decoded.as_ref().map(|(image, _)| image) != Some(&job.image) {
+                let image = match image::open(&job.image) {
+                    Ok(image) => image,
+                    Err(ImageError::Unsupported(err)) => {
+                        // Better the whole image on every monitor than none
+                        warn!(
+                            "Can't cut {} into slices: {err}",
+                            job.image.to_string_lossy()
+                        );
+                        continue;
+                    }
+                    Err(_) => return Err(Error::InvalidImage(job.image.clone())),
+                };
+                decoded = Some((job.image.clone(), image));
+            }
+            let (_, image) = decoded.as_ref().unwrap();
+            let (x, y, width, height) = slice.region(image.width(), image.height());
+            debug!(
+                "Cutting {width}x{height}+{x}+{y} out of {}",
+                job.image.to_string_lossy()
+            );
+            save(&image.crop_imm(x, y, width, height), &path)?;
+            written = true;
+        }
+        job.image = path;
+    }
+
+    if written {
+        prune(&cache_dir());
+    }
+    Ok(())
+}
+
+/// Slices are named after the image, its modification time and the slice
+fn cache_path(image: &Path, slice: &Slice) -> Result<PathBuf, Error> {
+    let modified = fs::metadata(image)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|_| Error::InvalidImage(image.to_path_buf()))?;
+    let mut hasher = DefaultHasher::new();
+    image.hash(&mut hasher);
+    modified.hash(&mut hasher);
+    slice.output.hash_bits(&mut hasher);
+    slice.layout.hash_bits(&mut hasher);
+    Ok(cache_dir().join(format!("{:016x}.png", hasher.finish())))
+}
+
+fn save(image: &DynamicImage, path: &Path) -> Result<(), Error> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(io::Error::other)?;
+    write_atomic(path, &png)?;
+    Ok(())
+}
+
+/// Delete the oldest slices when there are too many
+fn prune(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut slices: Vec<_> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((modified, entry.path()))
+        })
+        .collect();
+    if slices.len() <= MAX_CACHED_SLICES {
+        return;
+    }
+    slices.sort();
+    for (_, path) in &slices[..slices.len() - MAX_CACHED_SLICES] {
+        if let Err(err) = fs::remove_file(path) {
+            warn!("Couldn't delete {}: {err}", path.to_string_lossy());
+        }
+    }
+}
+
+fn cache_dir() -> PathBuf {
+    daemon::xdg_dir("XDG_CACHE_HOME", ".cache").join("slices")
+}
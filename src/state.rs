@@ -1,10 +1,32 @@
 #![warn(missing_docs)]
 use clap::clap_derive::ArgEnum;
+use inotify::Watches;
 use log::{error, info, trace, warn};
-use rand::Rng;
-use std::{collections::VecDeque, fs, path::PathBuf, process::Command, time::Duration};
-
-use crate::daemon::{Config, DaemonArgs};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::backend::{Backend, CommandLine, Job};
+use crate::bag::ShuffleBag;
+use crate::config::Config;
+use crate::error::Error;
+use crate::index::IndexOptions;
+use crate::library::{Library, List};
+use crate::monitors::Monitor;
+use crate::protocol::{Event, EventKind, Status};
+use crate::select::{
+    External, Favorites, LeastRecent, Linear, Random, Selection, SelectorQuery, Shuffle,
+    Strategies, Weighted,
+};
+use crate::sources::{SourceConfig, Sources, DEFAULT_SOURCE};
+use crate::span::{self, Slice};
+use crate::stats::{self, DisplayEvent, Report};
+use crate::worker::{Pending, Worker};
 
 #[derive(Debug)]
 struct History {
@@ -46,42 +68,67 @@ impl History {
         }
         self.previous.push_back(path);
     }
+}
 
-    fn contains(&self, path: &PathBuf) -> bool {
-        self.previous.contains(path)
-    }
+/// Part of the state which survives restarts of the daemon
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedState {
+    previous: VecDeque<PathBuf>,
+    next: Vec<PathBuf>,
+    action: NextImage,
+    previous_action: NextImage,
+    use_fallback: bool,
+    /// Interval in seconds
+    change_interval: u64,
+    /// Only saved when there is a single source, so `wp wp-dir` survives restarts
+    #[serde(default)]
+    image_dir: Option<PathBuf>,
+    /// Sources disabled through `wp source disable`
+    #[serde(default)]
+    disabled: Vec<String>,
+    /// Direction of linear mode when ping-ponging
+    #[serde(default)]
+    backwards: bool,
+    /// Images left in the current round of shuffle mode
+    #[serde(default)]
+    bag: Vec<PathBuf>,
+    /// What the config said when the state was saved, interval, mode and wallpaper directory
+    /// are only restored while it still says the same
+    #[serde(default)]
+    configured: Option<Configured>,
 }
 
-#[derive(Debug)]
-pub struct WallpaperCommands {
-    pub wallpaper_cmd: String,
-    pub wallpaper_post_cmd: Option<String>,
-    pub wallpaper_post_offset: Option<usize>,
+/// Settings as the config has them, including the command line arguments
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Configured {
+    /// Interval in seconds
+    change_interval: u64,
+    action: NextImage,
+    /// Directory of the only source, `None` if there are several
+    image_dir: Option<PathBuf>,
 }
 
-impl WallpaperCommands {
-    pub fn new(args: &DaemonArgs, config: &Config) -> Self {
-        let wallpaper_cmd = args
-            .wallpaper_change_command
-            .as_ref()
-            .unwrap_or(&config.wallpaper_change_command);
-        let wallpaper_post_cmd = args
-            .wallpaper_post_change_command
-            .as_ref()
-            .or(config.wallpaper_post_change_command.as_ref());
-        let wallpaper_post_offset = args
-            .wallpaper_post_change_offset
-            .as_ref()
-            .or(config.wallpaper_post_change_offset.as_ref());
-
-        WallpaperCommands {
-            wallpaper_cmd: wallpaper_cmd.to_owned(),
-            wallpaper_post_cmd: wallpaper_post_cmd.cloned(),
-            wallpaper_post_offset: wallpaper_post_offset.cloned(),
+impl Configured {
+    fn new(config: &Config) -> Self {
+        Configured {
+            change_interval: config.interval,
+            action: config.mode,
+            image_dir: match &config.sources()[..] {
+                [(_, source)] => Some(expand_home(source.directory.clone())),
+                _ => None,
+            },
         }
     }
 }
 
+/// Settings given on the command line, they are never restored
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pinned {
+    pub interval: bool,
+    pub mode: bool,
+    pub image_dir: bool,
+}
+
 /// Global object to store the current state
 #[derive(Debug)]
 pub struct State {
@@ -89,17 +136,78 @@ pub struct State {
     action: NextImage,
     previous_action: NextImage,
     change_interval: Duration,
-    image_dir: PathBuf,
+    /// When the interval thread changes the image next
+    next_change: Instant,
+    /// Images in the wallpaper directories
+    sources: Sources,
     use_fallback: bool,
     default_image: PathBuf,
-    wallpaper_cmds: WallpaperCommands,
+    /// Runs the backend
+    worker: Worker,
+    /// Result of the last update
+    pending: Option<Pending>,
+    /// How long a wallpaper command may run
+    command_timeout: Duration,
+    /// Image which was last passed to the wallpaper command
+    shown: Option<PathBuf>,
+    /// Since when `shown` is shown
+    shown_since: Instant,
+    /// The image changes because of `wp next`
+    skipping: bool,
+    /// Connections listening for changes
+    subscribers: Vec<Sender<Event>>,
+    /// File to persist the state to
+    state_file: Option<PathBuf>,
+    /// What the config says about the settings which are saved
+    configured: Configured,
+    /// Output this state draws on, all outputs if not set
+    output: Option<String>,
+    /// Monitors this state draws on, `None` until they were discovered
+    monitors: Option<Vec<Monitor>>,
+    /// Show one slice of the image on every monitor
+    span: bool,
+    aspect_ratio: AspectRatio,
+    /// Ratings, favorites and banned images, shared by all outputs
+    library: Arc<Mutex<Library>>,
+    strategies: Strategies,
+    /// Allowed relative difference between the aspect ratios of image and output
+    aspect_ratio_tolerance: f64,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy, ArgEnum)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, ArgEnum, Serialize, Deserialize)]
 pub enum NextImage {
+    #[serde(alias = "random")]
     Random,
+    #[serde(alias = "linear")]
     Linear,
+    #[serde(alias = "static")]
     Static,
+    /// Every image once in random order, then the next round
+    #[serde(alias = "shuffle")]
+    Shuffle,
+    /// Ask the selector command
+    #[serde(alias = "external")]
+    External,
+    /// Like random, but images with more stars more often
+    #[serde(alias = "weighted")]
+    Weighted,
+    /// Only favorites, every one once before repeating any
+    #[serde(alias = "favorites")]
+    Favorites,
+    /// The images which weren't shown for the longest time
+    #[serde(alias = "least-recent")]
+    LeastRecent,
+}
+
+/// How the aspect ratio of the output is taken into account when choosing images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AspectRatio {
+    Ignore,
+    /// Choose matching images, others once every matching image was shown recently
+    Prefer,
+    /// Only choose matching images, unless there are none
+    Require,
 }
 
 pub enum ChangeImageDirection {
@@ -108,57 +216,230 @@ pub enum ChangeImageDirection {
 }
 
 impl State {
+    /// State for `output` using the settings of `config`
     pub fn new(
-        change_interval: Duration,
-        image_dir: PathBuf,
-        default_image: PathBuf,
-        action: NextImage,
-        wallpaper_cmds: WallpaperCommands,
-        history_max_size: usize,
+        config: &Config,
+        backend: Box<dyn Backend>,
+        output: Option<String>,
+        library: Arc<Mutex<Library>>,
     ) -> Self {
+        let change_interval = Duration::from_secs(config.interval);
+        if config.sets.is_some() && output.is_some() {
+            warn!("Image sets only work when all outputs share one rotation");
+        }
+        let mut options = config.index_options();
+        options.sets = options.sets.filter(|_| output.is_none());
+        let sources = Sources::build(config.sources(), options);
+        let default_image = expand_home(config.default_image.clone());
+
         let mut history = VecDeque::new();
         history.push_back(default_image.clone());
 
-        let image_dir = if image_dir.starts_with("~/") {
-            let mut home = PathBuf::from(std::env::var("HOME").unwrap());
-            home.push(image_dir.components().skip(1).collect::<PathBuf>());
-            home
-        } else {
-            image_dir
-        };
-
-        let default_image = if default_image.starts_with("~/") {
-            let mut home = PathBuf::from(std::env::var("HOME").unwrap());
-            home.push(default_image.components().skip(1).collect::<PathBuf>());
-            home
-        } else {
-            default_image
-        };
-
-        State {
+        let mut state = State {
             history: History {
                 previous: history,
                 next: Vec::new(),
-                history_max_size,
+                history_max_size: config.history_length,
             },
-            action,
-            previous_action: action,
+            action: config.mode,
+            previous_action: config.mode,
             change_interval,
-            image_dir,
+            next_change: Instant::now() + change_interval,
+            sources,
             use_fallback: false,
             default_image,
-            wallpaper_cmds,
+            worker: Worker::new(backend),
+            pending: None,
+            command_timeout: Duration::from_secs(config.command_timeout),
+            shown: None,
+            shown_since: Instant::now(),
+            skipping: false,
+            subscribers: Vec::new(),
+            state_file: None,
+            configured: Configured::new(config),
+            output,
+            monitors: None,
+            span: false,
+            aspect_ratio: config.aspect_ratio,
+            library,
+            strategies: Strategies {
+                random: Random,
+                weighted: Weighted {
+                    unrated_weight: config.unrated_weight,
+                },
+                linear: Linear {
+                    ping_pong: config.ping_pong,
+                    backwards: false,
+                },
+                shuffle: Shuffle::default(),
+                least_recent: LeastRecent,
+                favorites: Favorites::default(),
+                external: External {
+                    command: config.selector_command.clone(),
+                },
+            },
+            aspect_ratio_tolerance: config.aspect_ratio_tolerance,
+        };
+        state.set_span(config.span);
+        let layout = state.own_monitors(&config.layout());
+        if !layout.is_empty() {
+            state.monitors = Some(layout);
         }
+        state
     }
 
-    pub fn change_image(&mut self, direction: ChangeImageDirection) {
-        if self.use_fallback {
-            info!("Can't change image while using fallback");
+    /// Persist the state in `path` and restore it if it was saved before
+    /// Returns whether there was a state to restore
+    ///
+    /// Interval, mode and wallpaper directory stay as configured if they are `pinned`
+    /// or the config changed them since they were saved.
+    pub fn restore(&mut self, path: PathBuf, pinned: Pinned) -> Result<bool, Error> {
+        self.state_file = Some(path.clone());
+        if !path.is_file() {
+            return Ok(false);
+        }
+
+        let saved: SavedState = serde_json::from_str(&fs::read_to_string(&path)?)
+            .map_err(|err| Error::Io(err.into()))?;
+        info!("Restoring state from {}", path.to_string_lossy());
+
+        let mut previous = saved.previous;
+        while previous.len() > self.history.history_max_size {
+            previous.pop_front();
+        }
+        if !previous.is_empty() {
+            self.history.previous = previous;
+            self.history.next = saved.next;
+        }
+        let configured = saved.configured.as_ref();
+        let unchanged = |pinned: bool, same: fn(&Configured, &Configured) -> bool| {
+            !pinned && configured.is_some_and(|configured| same(configured, &self.configured))
+        };
+        let restore_interval = unchanged(pinned.interval, |a, b| {
+            a.change_interval == b.change_interval
+        });
+        let restore_action = unchanged(pinned.mode, |a, b| a.action == b.action);
+        let restore_dir = unchanged(pinned.image_dir, |a, b| a.image_dir == b.image_dir);
+
+        self.use_fallback = saved.use_fallback;
+        if restore_action {
+            self.action = saved.action;
+            self.previous_action = saved.previous_action;
+        } else if self.use_fallback {
+            self.previous_action = self.action;
+            self.action = NextImage::Static;
+        }
+        self.strategies.linear.backwards = saved.backwards && self.strategies.linear.ping_pong;
+        if restore_interval {
+            self.change_interval = Duration::from_secs(saved.change_interval);
+        }
+        self.next_change = Instant::now() + self.change_interval;
+        for name in &saved.disabled {
+            // The source may have been removed from the config since
+            let _ = self.sources.set_enabled(name, false);
+        }
+        match saved.image_dir {
+            Some(dir)
+                if restore_dir
+                    && self.sources.configs().len() == 1
+                    && dir != *self.get_image_dir() =>
+            {
+                if let Err(err) = self.replace_sources(dir) {
+                    warn!("Couldn't restore the saved wallpaper directory: {err}");
+                }
+            }
+            _ => {}
+        }
+        self.strategies.shuffle.bag = ShuffleBag::restore(saved.bag, &self.sources.images());
+        Ok(true)
+    }
+
+    /// Write the state to the state file
+    pub fn store(&self) {
+        let Some(path) = &self.state_file else {
             return;
+        };
+        let saved = SavedState {
+            previous: self.history.previous.clone(),
+            next: self.history.next.clone(),
+            action: self.action,
+            previous_action: self.previous_action,
+            use_fallback: self.use_fallback,
+            change_interval: self.change_interval.as_secs(),
+            image_dir: Some(self.get_image_dir().clone())
+                .filter(|_| self.sources.configs().len() == 1),
+            disabled: self.sources.disabled(),
+            backwards: self.strategies.linear.backwards,
+            bag: self.strategies.shuffle.bag.remaining().to_vec(),
+            configured: Some(self.configured.clone()),
+        };
+        if let Err(err) = write_atomic(path, &serde_json::to_vec(&saved).unwrap()) {
+            error!("Couldn't save state to {}: {err}", path.to_string_lossy());
+        }
+    }
+
+    /// Go to the next image because the user asked for it, which counts as skipping the current one
+    /// `selected` is the answer to the [`State::selector_query`] asked before
+    pub fn skip(&mut self, selected: Option<Result<PathBuf, Error>>) -> Result<(), Error> {
+        self.skipping = true;
+        let result = match selected {
+            Some(selected) => self.show_selected(selected),
+            None => self.change_image(ChangeImageDirection::Next),
+        };
+        self.skipping = false;
+        result
+    }
+
+    /// What external mode asks its selector command before going to the next image,
+    /// `None` in other modes or when going forward in the history
+    /// Run it without holding the lock and pass the answer to [`State::show_selected`].
+    pub fn selector_query(&mut self) -> Option<Result<SelectorQuery, Error>> {
+        if !self.asks_selector() {
+            return None;
+        }
+        let banned = self.banned();
+        let (output, timeout) = (self.output.clone(), self.command_timeout);
+        Some(self.selection(&banned).and_then(|(selection, strategies)| {
+            strategies
+                .external
+                .query(&selection, output.as_deref(), timeout)
+        }))
+    }
+
+    /// Go to the image the selector command chose, see [`State::selector_query`]
+    pub fn show_selected(&mut self, selected: Result<PathBuf, Error>) -> Result<(), Error> {
+        // The state may have changed while the command ran
+        if !self.asks_selector() {
+            return self.change_image(ChangeImageDirection::Next);
+        }
+        let image = selected?;
+        if self.library.lock().unwrap().banned.contains(&image) {
+            let err = Error::Banned(image);
+            warn!("The selector command chose an image which can't be shown: {err}");
+            return self.change_image(ChangeImageDirection::Next);
+        }
+        if !self.sources.contains(&image) {
+            warn!(
+                "The selector command chose {}, which isn't in the wallpaper directories",
+                image.to_string_lossy()
+            );
+        }
+        info!("Going to the next image");
+        self.history.push_back(image);
+        self.update();
+        Ok(())
+    }
+
+    fn asks_selector(&self) -> bool {
+        self.action == NextImage::External && !self.use_fallback && !self.history.has_next()
+    }
+
+    pub fn change_image(&mut self, direction: ChangeImageDirection) -> Result<(), Error> {
+        if self.use_fallback {
+            return Err(Error::Fallback);
         }
         if let NextImage::Static = self.action {
-            info!("Can't change image while in static mode");
-            return;
+            return Err(Error::StaticMode);
         }
 
         match direction {
@@ -168,35 +449,8 @@ impl State {
                 if self.history.has_next() {
                     self.history.go_next();
                 } else {
-                    let num_pics = fs::read_dir(&self.image_dir).unwrap().count();
-
-                    loop {
-                        let idx = if self.action == NextImage::Random {
-                            rand::thread_rng().gen_range(0..num_pics)
-                        } else {
-                            let mut idx = fs::read_dir(&self.image_dir)
-                                .unwrap()
-                                .filter_map(|res| res.ok().map(|e| e.path()))
-                                .position(|elem| elem == *self.history.previous.back().unwrap())
-                                .unwrap_or(0);
-                            idx += 1;
-                            idx %= num_pics;
-                            idx
-                        };
-
-                        let wallpaper_path = fs::read_dir(&self.image_dir)
-                            .unwrap()
-                            .filter_map(|res| res.ok().map(|e| e.path()))
-                            .nth(idx)
-                            .unwrap();
-
-                        if !self.history.contains(&wallpaper_path)
-                            || num_pics <= self.history.history_max_size
-                        {
-                            self.history.push_back(wallpaper_path);
-                            break;
-                        }
-                    }
+                    let wallpaper_path = self.pick_image()?;
+                    self.history.push_back(wallpaper_path);
                 }
             }
             ChangeImageDirection::Previous => {
@@ -204,58 +458,160 @@ impl State {
                 if self.history.has_previous() {
                     self.history.go_previous();
                 } else {
-                    info!("There is no previous image");
+                    return Err(Error::NoPrevious);
                 }
             }
         }
 
         // Update current image
-        if self.update().is_err() {
-            error!("Error setting the wallpaper");
+        self.update();
+        Ok(())
+    }
+
+    /// Choose a new image from the image directory, using the strategy of the current mode
+    fn pick_image(&mut self) -> Result<PathBuf, Error> {
+        let banned = self.banned();
+        let action = self.action;
+        let (selection, strategies) = self.selection(&banned)?;
+        strategies
+            .get(action)
+            .ok_or(Error::StaticMode)?
+            .select(&selection)
+    }
+
+    /// What the strategies choose from when `banned` are the banned images
+    fn selection<'a>(
+        &'a mut self,
+        banned: &'a HashSet<PathBuf>,
+    ) -> Result<(Selection<'a>, &'a mut Strategies), Error> {
+        let target = self.target_aspect_ratio();
+        let sources = &self.sources;
+        let in_sources = banned
+            .iter()
+            .filter(|image| sources.contains(image))
+            .count();
+        if sources.len() <= in_sources && self.action != NextImage::Favorites {
+            return Err(Error::NoImages(self.get_image_dir().clone()));
         }
+        let matching = target.and_then(|target| {
+            matching_images(sources, banned, target, self.aspect_ratio_tolerance)
+        });
+        let selection = Selection::new(
+            sources,
+            matching,
+            self.aspect_ratio == AspectRatio::Prefer,
+            &self.history.previous,
+            banned,
+            &self.library,
+        );
+        Ok((selection, &mut self.strategies))
+    }
+
+    /// Snapshot of the banned images, so the library isn't locked while choosing
+    fn banned(&self) -> HashSet<PathBuf> {
+        let library = self.library.lock().unwrap();
+        library.banned.images().iter().cloned().collect()
     }
 
-    pub fn update(&self) -> Result<(), ()> {
+    /// Aspect ratio images should have, `None` if it doesn't matter
+    fn target_aspect_ratio(&self) -> Option<f64> {
+        if self.aspect_ratio == AspectRatio::Ignore {
+            return None;
+        }
+        match self.monitors.as_deref()? {
+            [] => None,
+            monitors if self.span => {
+                let layout = span::layout(monitors);
+                Some(layout.width / layout.height)
+            }
+            [monitor] => {
+                let (width, height) = monitor.size();
+                Some(f64::from(width) / f64::from(height))
+            }
+            // Every monitor shows the same image
+            _ => None,
+        }
+    }
+
+    /// Hand the current image to the worker, see [`State::take_pending`] for the result
+    pub fn update(&mut self) {
         info!("Updating current wallpaper");
         let path = self.get_current_image();
         trace!("setting wallpaper to {}", path.to_string_lossy());
 
-        let wallpaper_cmd = self
-            .wallpaper_cmds
-            .wallpaper_cmd
-            .replace("%wallpaper%", path.to_str().unwrap());
-
-        trace!("Calling {:?}", wallpaper_cmd);
-        let _process = Command::new("sh")
-            .arg("-c")
-            .arg(wallpaper_cmd)
-            .output()
-            .unwrap();
-
-        if let Some(delay) = self.wallpaper_cmds.wallpaper_post_offset {
-            if let Some(command) = &self.wallpaper_cmds.wallpaper_post_cmd {
-                if let Some(prev) = self.history.previous.iter().rev().nth(delay) {
-                    let prev = command.replace("%wallpaper%", prev.to_str().unwrap());
-                    trace!("Calling {:?}", prev);
-                    let _process = Command::new("sh").arg("-c").arg(&prev).output().unwrap();
-                }
+        let job = |image: &PathBuf, output: Option<String>, slice: Option<Slice>| Job {
+            image: image.clone(),
+            output,
+            slice,
+            history: self.history.previous.clone(),
+            timeout: self.command_timeout,
+        };
+        let jobs = match (self.sources.sets(), self.sources.members(path)) {
+            // Every image of a set goes to its own output
+            (Some(sets), Some(members)) => members
+                .iter()
+                .map(|(role, image)| job(image, Some(sets.output(role)), None))
+                .collect(),
+            _ => match self.monitors.as_deref() {
+                Some(monitors) if self.span && !monitors.is_empty() => span::slices(monitors)
+                    .into_iter()
+                    .zip(monitors)
+                    .map(|(slice, monitor)| job(path, Some(monitor.name.clone()), Some(slice)))
+                    .collect(),
+                _ => vec![job(path, self.output.clone(), None)],
+            },
+        };
+        self.pending = Some(self.worker.apply(jobs));
+
+        if self.shown.as_ref() != Some(self.get_current_image()) {
+            let mut library = self.library.lock().unwrap();
+            if let Some(shown) = &self.shown {
+                let duration = self.shown_since.elapsed();
+                library.stats.ended(DisplayEvent {
+                    image: shown.clone(),
+                    start: stats::seconds(SystemTime::now() - duration),
+                    duration: duration.as_secs(),
+                    skipped: self.skipping,
+                    source: self.sources.dir_of(shown).cloned(),
+                });
             }
+            library.stats.started(self.get_current_image());
+            drop(library);
+            self.shown = Some(self.get_current_image().clone());
+            self.shown_since = Instant::now();
+            self.notify(EventKind::Wallpaper);
         }
-        Ok(())
     }
 
-    pub fn update_action(&mut self, action: NextImage, image: Option<PathBuf>) {
+    /// Result of the last [`State::update`]
+    /// Wait for it after unlocking the state
+    pub fn take_pending(&mut self) -> Option<Pending> {
+        self.pending.take()
+    }
+
+    pub fn update_action(
+        &mut self,
+        action: NextImage,
+        image: Option<PathBuf>,
+    ) -> Result<(), Error> {
+        if let Some(image) = &image {
+            if !image.is_file() {
+                return Err(Error::InvalidImage(image.clone()));
+            }
+        }
         info!("Setting action to {:?}", action);
-        self.action = action;
+        if self.action != action {
+            self.action = action;
+            self.notify(EventKind::Mode);
+        }
         if let Some(image) = image {
             self.history.push_back(image);
-            if self.update().is_err() {
-                error!("Error setting the wallpaper");
-            }
+            self.update();
         }
+        Ok(())
     }
 
-    pub fn save(&mut self) {
+    pub fn save(&mut self) -> Result<(), Error> {
         self.use_fallback = !self.use_fallback;
         info!("Setting fallback to {}", self.use_fallback);
         if self.use_fallback {
@@ -266,37 +622,373 @@ impl State {
             self.action = self.previous_action;
             self.history.previous.pop_back();
         }
-        if self.update().is_err() {
-            error!("Error setting the wallpaper");
+        self.notify(EventKind::Fallback);
+        self.update();
+        Ok(())
+    }
+
+    /// Show images from `dir` instead of the configured sources
+    pub fn set_image_dir(&mut self, dir: PathBuf) -> Result<(), Error> {
+        self.replace_sources(dir)?;
+        self.notify(EventKind::WpDir);
+        self.update();
+        Ok(())
+    }
+
+    fn replace_sources(&mut self, dir: PathBuf) -> Result<(), Error> {
+        let dir = expand_home(dir);
+        if !dir.exists() {
+            return Err(Error::InvalidDirectory(dir));
         }
+        let options = self.sources.options().clone();
+        self.sources.configure(
+            vec![(DEFAULT_SOURCE.to_owned(), SourceConfig::new(dir))],
+            options,
+        );
+        Ok(())
+    }
+
+    /// Use other sources, scan rules or image sets
+    pub fn set_sources(&mut self, sources: Vec<(String, SourceConfig)>, mut options: IndexOptions) {
+        options.sets = options.sets.filter(|_| self.output.is_none());
+        self.sources.configure(sources, options);
+        self.notify(EventKind::WpDir);
+        self.update();
+    }
+
+    /// Enable or disable a source until the daemon restarts or the config changes it
+    pub fn set_source_enabled(&mut self, name: &str, enabled: bool) -> Result<(), Error> {
+        self.sources.set_enabled(name, enabled)?;
+        info!(
+            "{} source {name}",
+            if enabled { "Enabling" } else { "Disabling" }
+        );
+        self.notify(EventKind::Sources);
+        Ok(())
+    }
+
+    /// The candidate images in linear order, or the history
+    /// Sets are replaced by their images, so reading the playlist results in the same sets
+    pub fn playlist(&self, history: bool) -> Vec<PathBuf> {
+        let entries: Vec<&PathBuf> = if history {
+            self.history.previous.iter().collect()
+        } else {
+            let library = self.library.lock().unwrap();
+            self.sources
+                .images()
+                .into_iter()
+                .filter(|image| !library.banned.contains(image))
+                .collect()
+        };
+        entries
+            .into_iter()
+            .flat_map(|entry| match self.sources.members(entry) {
+                Some(members) => members.values().cloned().collect(),
+                None => vec![entry.clone()],
+            })
+            .collect()
+    }
+
+    /// One line per source
+    pub fn describe_sources(&self) -> Vec<String> {
+        self.sources.describe()
     }
 
-    pub fn set_image_dir(&mut self, dir: PathBuf) {
-        self.image_dir = dir;
-        self.update().unwrap();
+    pub fn set_backend(&mut self, backend: Box<dyn Backend>) {
+        self.worker.set_backend(backend);
     }
 
+    pub fn set_command_timeout(&mut self, timeout: Duration) {
+        self.command_timeout = timeout;
+    }
+
+    pub fn set_default_image(&mut self, image: PathBuf) {
+        self.default_image = expand_home(image);
+    }
+
+    pub fn set_history_length(&mut self, length: usize) {
+        self.history.history_max_size = length;
+        while self.history.previous.len() > length.max(1) {
+            self.history.previous.pop_front();
+        }
+    }
+
+    /// Directory of the first source
     pub fn get_image_dir(&self) -> &PathBuf {
-        &self.image_dir
+        self.sources.dir()
+    }
+
+    /// Every wallpaper directory with the number of candidate images in it
+    pub fn describe_image_dirs(&self) -> Vec<String> {
+        self.sources.describe_dirs()
+    }
+
+    /// Keep the image index up to date through the given inotify instance
+    pub fn watch_images(&mut self, watches: Watches) {
+        self.sources.watch(watches);
+    }
+
+    pub fn sources_mut(&mut self) -> &mut Sources {
+        &mut self.sources
     }
 
     pub fn get_current_image(&self) -> &PathBuf {
         self.history.previous.back().unwrap()
     }
 
+    /// Update the monitors, keeping those this state draws on
+    /// Monitors which were plugged in since the last discovery get the current wallpaper
+    pub fn set_monitors(&mut self, monitors: &[Monitor]) {
+        let monitors = monitors
+            .iter()
+            .filter(|monitor| {
+                self.output
+                    .as_ref()
+                    .is_none_or(|name| *name == monitor.name)
+            })
+            .cloned()
+            .collect();
+        let known = self.monitors.replace(monitors);
+        let monitors = self.monitors.as_ref().unwrap();
+        if self.span && known.as_ref() != Some(monitors) {
+            info!("Monitor layout changed, cutting the image again");
+            self.update();
+            return;
+        }
+        let Some(known) = known else {
+            return;
+        };
+        let connected: Vec<&str> = monitors
+            .iter()
+            .filter(|monitor| !known.iter().any(|known| known.name == monitor.name))
+            .map(|monitor| monitor.name.as_str())
+            .collect();
+        if !connected.is_empty() {
+            info!("Monitor {} connected", connected.join(", "));
+            self.update();
+        }
+    }
+
+    fn own_monitors(&self, monitors: &[Monitor]) -> Vec<Monitor> {
+        monitors
+            .iter()
+            .filter(|monitor| {
+                self.output
+                    .as_ref()
+                    .is_none_or(|name| *name == monitor.name)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Remember what the config says about the settings which are saved, see [`State::restore`]
+    pub fn set_configured(&mut self, config: &Config) {
+        self.configured = Configured::new(config);
+    }
+
+    pub fn set_ping_pong(&mut self, ping_pong: bool) {
+        self.strategies.linear.ping_pong = ping_pong;
+        self.strategies.linear.backwards &= ping_pong;
+    }
+
+    pub fn set_unrated_weight(&mut self, weight: f64) {
+        self.strategies.weighted.unrated_weight = weight;
+    }
+
+    /// Ratings, favorites and banned images, shared by all outputs
+    pub fn library(&self) -> Arc<Mutex<Library>> {
+        self.library.clone()
+    }
+
+    /// Rate the current image with `stars`
+    pub fn rate(&mut self, stars: u8) -> Result<(), Error> {
+        let image = self.get_current_image().clone();
+        self.library.lock().unwrap().ratings.rate(&image, stars)?;
+        self.notify(EventKind::Rating);
+        Ok(())
+    }
+
+    /// Add `image` to `list`, or remove it, the current image if not given
+    /// Banning the current image shows the next one
+    pub fn mark(&mut self, list: List, image: Option<PathBuf>, marked: bool) -> Result<(), Error> {
+        let image = image.unwrap_or_else(|| self.get_current_image().clone());
+        let mut library = self.library.lock().unwrap();
+        if !marked {
+            if !library.list_mut(list).remove(&image) {
+                return Err(Error::NotInList(image, list));
+            }
+            return Ok(());
+        }
+        if !image.is_file() {
+            return Err(Error::InvalidImage(image));
+        }
+        info!("Adding {} to {list:?}", image.to_string_lossy());
+        library.list_mut(list).add(&image);
+        drop(library);
+        if list == List::Banned {
+            self.skip_banned()?;
+        }
+        Ok(())
+    }
+
+    /// The images in `list`, in the order they were added
+    pub fn list(&self, list: List) -> Vec<PathBuf> {
+        self.library.lock().unwrap().list(list).images().to_vec()
+    }
+
+    /// Summary of the display statistics, see [`crate::stats::DisplayStats::report`]
+    pub fn report(&self, images: &[PathBuf], top: usize) -> Result<Report, Error> {
+        Ok(self.library.lock().unwrap().stats.report(images, top)?)
+    }
+
+    /// Remove banned images from the history and move on if the current image is banned
+    fn skip_banned(&mut self) -> Result<(), Error> {
+        let library = self.library.clone();
+        let banned = |image: &PathBuf| library.lock().unwrap().banned.contains(image);
+        self.history.next.retain(|image| !banned(image));
+        if banned(self.get_current_image())
+            && !self.use_fallback
+            && self.action != NextImage::Static
+        {
+            self.change_image(ChangeImageDirection::Next)?;
+        }
+        // Keep the current image, the history is never empty
+        let current = self.history.previous.pop_back().unwrap();
+        self.history.previous.retain(|image| !banned(image));
+        self.history.previous.push_back(current);
+        Ok(())
+    }
+
+    /// Stars of the current image, `None` if it wasn't rated
+    pub fn rating(&self) -> Option<u8> {
+        self.library
+            .lock()
+            .unwrap()
+            .ratings
+            .get(self.get_current_image())
+    }
+
+    pub fn set_selector_command(&mut self, command: Option<CommandLine>) {
+        self.strategies.external.command = command;
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: AspectRatio, tolerance: f64) {
+        self.aspect_ratio = aspect_ratio;
+        self.aspect_ratio_tolerance = tolerance;
+    }
+
+    /// Spanning only works when this state draws on all outputs
+    pub fn set_span(&mut self, span: bool) {
+        if span && self.output.is_some() {
+            warn!("Can't span one image across a single output");
+        }
+        self.span = span && self.output.is_none();
+    }
+
+    pub fn get_output(&self) -> Option<&str> {
+        self.output.as_deref()
+    }
+
     pub fn get_action(&self) -> NextImage {
         self.action
     }
 
     pub fn change_interval(&mut self, i: Duration) {
-        self.change_interval = i;
+        if self.change_interval != i {
+            self.change_interval = i;
+            self.notify(EventKind::Duration);
+        }
     }
 
     pub fn get_change_interval(&self) -> Duration {
         self.change_interval
     }
 
+    /// Start waiting for the next change, returns how long to wait
+    pub fn schedule_next_change(&mut self) -> Duration {
+        self.next_change = Instant::now() + self.change_interval;
+        self.change_interval
+    }
+
     pub fn get_fallback(&self) -> bool {
         self.use_fallback
     }
+
+    pub fn status(&self) -> Status {
+        Status {
+            output: self.output.clone(),
+            wallpaper: self.get_current_image().clone(),
+            duration: self.change_interval.as_secs(),
+            remaining: self
+                .next_change
+                .saturating_duration_since(Instant::now())
+                .as_secs(),
+            mode: self.action,
+            fallback: self.use_fallback,
+            wp_dir: self.get_image_dir().clone(),
+            images: self.sources.len(),
+            history: self.history.previous.len(),
+            rating: self.rating(),
+        }
+    }
+
+    /// Send an [`Event`] every time the state changes
+    pub fn subscribe(&mut self, subscriber: Sender<Event>) {
+        self.subscribers.push(subscriber);
+    }
+
+    fn notify(&mut self, event: EventKind) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let event = Event {
+            event,
+            status: self.status(),
+        };
+        // Drop subscribers which disconnected
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+/// The images of `sources` with the aspect ratio `target`, `None` if no image has it
+fn matching_images<'a>(
+    sources: &'a Sources,
+    banned: &HashSet<PathBuf>,
+    target: f64,
+    tolerance: f64,
+) -> Option<Vec<&'a PathBuf>> {
+    let matching: Vec<&PathBuf> = sources
+        .images()
+        .into_iter()
+        .filter(|image| {
+            !banned.contains(*image)
+                && sources
+                    .aspect_ratio(image)
+                    .is_some_and(|ratio| (ratio / target - 1.0).abs() <= tolerance)
+        })
+        .collect();
+    if matching.is_empty() {
+        info!("No image has an aspect ratio close to {target:.2}, choosing from all images");
+        return None;
+    }
+    Some(matching)
+}
+
+/// Replace a leading `~/` with the home directory
+pub fn expand_home(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var("HOME")) {
+        (Ok(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => path,
+    }
+}
+
+/// Write to a temporary file first so a crash never leaves a truncated file behind
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
 }
@@ -0,0 +1,234 @@
+//! When and for how long images were shown, saved in the state directory
+//!
+//! Besides the totals per image every showing is appended to an event log,
+//! which `wp stats` summarizes.
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::state::write_atomic;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageStats {
+    /// Seconds since the epoch
+    pub last_shown: u64,
+    /// Seconds the image was shown in total
+    pub shown_for: u64,
+    pub times_shown: u64,
+}
+
+/// One showing of an image, one JSON object per line in the event log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayEvent {
+    pub image: PathBuf,
+    /// Seconds since the epoch
+    pub start: u64,
+    /// Seconds
+    pub duration: u64,
+    /// The user moved on with `wp next`
+    pub skipped: bool,
+    /// Directory or playlist of the source the image is from
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+}
+
+/// Summary of the event log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    /// Number of showings in the event log
+    pub events: usize,
+    pub most_shown: Vec<ImageCount>,
+    pub most_skipped: Vec<ImageCount>,
+    /// The first `top` images of the sources which were never shown
+    pub never_shown: Vec<PathBuf>,
+    /// Number of images of the sources which were never shown
+    pub never_shown_count: usize,
+    /// Seconds images of every source were shown, the longest first
+    pub sources: Vec<SourceTime>,
+    /// Number of images which were skipped 0-10%, 10-20%, … 90-100% of the times they were shown
+    pub skip_rates: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageCount {
+    pub image: PathBuf,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceTime {
+    /// Not set for images which weren't in any source, like the default image
+    pub source: Option<PathBuf>,
+    pub seconds: u64,
+}
+
+/// Statistics of every image which was shown, by path
+#[derive(Debug, Default)]
+pub struct DisplayStats {
+    file: Option<PathBuf>,
+    images: HashMap<PathBuf, ImageStats>,
+    /// Event log, [`DisplayEvent`]s are appended to it
+    log: Option<PathBuf>,
+}
+
+impl DisplayStats {
+    /// Read the statistics saved in `file`, none if there is none, and append events to `log`
+    pub fn load(file: PathBuf, log: PathBuf) -> Self {
+        let images = match fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                error!(
+                    "Ignoring invalid statistics in {}: {err}",
+                    file.to_string_lossy()
+                );
+                HashMap::new()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                error!(
+                    "Couldn't read statistics from {}: {err}",
+                    file.to_string_lossy()
+                );
+                HashMap::new()
+            }
+        };
+        DisplayStats {
+            file: Some(file),
+            images,
+            log: Some(log),
+        }
+    }
+
+    pub fn get(&self, image: &Path) -> Option<&ImageStats> {
+        self.images.get(image)
+    }
+
+    /// When the image was last shown in seconds since the epoch, 0 if it never was
+    pub fn last_shown(&self, image: &Path) -> u64 {
+        self.get(image).map_or(0, |stats| stats.last_shown)
+    }
+
+    /// The image is shown from now on
+    pub fn started(&mut self, image: &Path) {
+        let stats = self.images.entry(image.to_path_buf()).or_default();
+        stats.last_shown = seconds(SystemTime::now());
+        stats.times_shown += 1;
+        self.store();
+    }
+
+    /// The image of `event` was replaced
+    /// The totals are saved when the next image starts
+    pub fn ended(&mut self, event: DisplayEvent) {
+        if let Some(stats) = self.images.get_mut(&event.image) {
+            stats.shown_for += event.duration;
+        }
+        let Some(log) = &self.log else {
+            return;
+        };
+        let append = || -> io::Result<()> {
+            if let Some(dir) = log.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut file = fs::OpenOptions::new().create(true).append(true).open(log)?;
+            writeln!(file, "{}", serde_json::to_string(&event).unwrap())
+        };
+        if let Err(err) = append() {
+            error!("Couldn't write to {}: {err}", log.to_string_lossy());
+        }
+    }
+
+    /// Summarize the event log, with the `top` images in each ranking
+    /// `images` are the images of the sources, to find the ones which were never shown
+    pub fn report(&self, images: &[PathBuf], top: usize) -> io::Result<Report> {
+        let mut events = 0;
+        // Times shown and skipped
+        let mut counts: HashMap<PathBuf, (usize, usize)> = HashMap::new();
+        let mut sources: BTreeMap<Option<PathBuf>, u64> = BTreeMap::new();
+        if let Some(log) = self.log.as_ref().filter(|log| log.is_file()) {
+            let mut invalid = 0;
+            for line in io::BufReader::new(fs::File::open(log)?).lines() {
+                let Ok(event) = serde_json::from_str::<DisplayEvent>(&line?) else {
+                    invalid += 1;
+                    continue;
+                };
+                events += 1;
+                let count = counts.entry(event.image).or_default();
+                count.0 += 1;
+                count.1 += usize::from(event.skipped);
+                *sources.entry(event.source).or_default() += event.duration;
+            }
+            if invalid > 0 {
+                warn!(
+                    "Ignored {invalid} invalid lines in {}",
+                    log.to_string_lossy()
+                );
+            }
+        }
+
+        let ranking = |count: fn(&(usize, usize)) -> usize| {
+            let mut ranking: Vec<ImageCount> = counts
+                .iter()
+                .map(|(image, counts)| ImageCount {
+                    image: image.clone(),
+                    count: count(counts),
+                })
+                .filter(|image| image.count > 0)
+                .collect();
+            ranking.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.image.cmp(&b.image)));
+            ranking.truncate(top);
+            ranking
+        };
+        let mut skip_rates = vec![0; 10];
+        for (shown, skipped) in counts.values() {
+            skip_rates[(skipped * 10 / shown).min(9)] += 1;
+        }
+        let mut sources: Vec<SourceTime> = sources
+            .into_iter()
+            .map(|(source, seconds)| SourceTime { source, seconds })
+            .collect();
+        sources.sort_by_key(|source| Reverse(source.seconds));
+
+        let never_shown: Vec<&PathBuf> = images
+            .iter()
+            .filter(|image| !self.images.contains_key(*image) && !counts.contains_key(*image))
+            .collect();
+        Ok(Report {
+            events,
+            most_shown: ranking(|(shown, _)| *shown),
+            most_skipped: ranking(|(_, skipped)| *skipped),
+            never_shown: never_shown
+                .iter()
+                .take(top)
+                .map(|image| (*image).clone())
+                .collect(),
+            never_shown_count: never_shown.len(),
+            sources,
+            skip_rates,
+        })
+    }
+
+    fn store(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if let Err(err) = write_atomic(file, &serde_json::to_vec(&self.images).unwrap()) {
+            error!(
+                "Couldn't save statistics to {}: {err}",
+                file.to_string_lossy()
+            );
+        }
+    }
+}
+
+/// Seconds since the epoch
+pub fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
@@ -0,0 +1,100 @@
+//! Applies wallpapers on a separate thread, so the state stays available while commands run
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use log::{error, trace};
+
+use crate::backend::{Backend, Job};
+use crate::error::Error;
+use crate::span;
+
+enum Message {
+    /// Jobs which together show one wallpaper, like the slices of a panorama
+    Apply(Vec<Job>, Sender<Result<(), Error>>),
+    SetBackend(Box<dyn Backend>),
+}
+
+/// Handle to the thread owning the backend
+#[derive(Debug)]
+pub struct Worker {
+    messages: Sender<Message>,
+}
+
+/// Result of a job which may still be running
+#[derive(Debug)]
+pub struct Pending(Receiver<Result<(), Error>>);
+
+impl Pending {
+    /// Block until the wallpaper was applied
+    pub fn wait(self) -> Result<(), Error> {
+        self.0
+            .recv()
+            .unwrap_or_else(|_| Err(Error::Command("The worker thread stopped".to_owned())))
+    }
+}
+
+impl Worker {
+    pub fn new(backend: Box<dyn Backend>) -> Self {
+        let (messages, receiver) = mpsc::channel();
+        thread::spawn(move || work(backend, receiver));
+        Worker { messages }
+    }
+
+    pub fn apply(&self, jobs: Vec<Job>) -> Pending {
+        let (sender, receiver) = mpsc::channel();
+        if self.messages.send(Message::Apply(jobs, sender)).is_err() {
+            error!("The worker thread stopped");
+        }
+        Pending(receiver)
+    }
+
+    pub fn set_backend(&self, backend: Box<dyn Backend>) {
+        if self.messages.send(Message::SetBackend(backend)).is_err() {
+            error!("The worker thread stopped");
+        }
+    }
+}
+
+// Thread: Run wallpaper commands
+fn work(mut backend: Box<dyn Backend>, messages: Receiver<Message>) {
+    while let Ok(message) = messages.recv() {
+        let mut jobs = None;
+        // Only the most recent wallpaper matters when several are queued
+        for message in std::iter::once(message).chain(messages.try_iter()) {
+            match message {
+                Message::SetBackend(new) => backend = new,
+                Message::Apply(next, result) => {
+                    if let Some((skipped, result)) = jobs.replace((next, result)) {
+                        for job in skipped {
+                            trace!("Skipping {}", job.image.to_string_lossy());
+                        }
+                        let _ = result.send(Ok(()));
+                    }
+                }
+            }
+        }
+
+        if let Some((mut jobs, result)) = jobs {
+            let outcome = match span::cut(&mut jobs) {
+                Ok(()) => apply(backend.as_mut(), &jobs),
+                Err(err) => {
+                    error!("Couldn't cut the image into slices: {err}");
+                    Err(err)
+                }
+            };
+            // The requester may not be waiting for the result
+            let _ = result.send(outcome);
+        }
+    }
+}
+
+fn apply(backend: &mut dyn Backend, jobs: &[Job]) -> Result<(), Error> {
+    let outcome = backend.apply_all(jobs);
+    if let Err(err) = &outcome {
+        let images: Vec<_> = jobs.iter().map(|job| job.image.to_string_lossy()).collect();
+        error!("Couldn't set {}: {err}", images.join(", "));
+    }
+    outcome
+}